@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS card_templates (
+  name VARCHAR(64) PRIMARY KEY,
+  source TEXT NOT NULL,
+  updated_at INTEGER NOT NULL
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS users (
+  id INTEGER PRIMARY KEY,
+  username VARCHAR(32) NOT NULL UNIQUE COLLATE NOCASE,
+  salt BLOB NOT NULL,
+  password_hash BLOB NOT NULL,
+  iterations INTEGER NOT NULL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+  token_hash BLOB PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  expires_at INTEGER NOT NULL
+);
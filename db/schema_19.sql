@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS chat_messages (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  room_id INTEGER NOT NULL,
+  user_id TEXT NOT NULL,
+  message TEXT NOT NULL,
+  sent_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS chat_messages_room ON chat_messages (room_id, id);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS pokemon_cache (
+  id INTEGER PRIMARY KEY,
+  name VARCHAR(50) NOT NULL,
+  weight INTEGER NOT NULL,
+  height INTEGER NOT NULL,
+  fetched_at INTEGER NOT NULL
+);
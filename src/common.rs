@@ -1,8 +1,12 @@
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
 #[cfg(test)]
 use rocket::local::blocking::Client;
+use rocket::serde::Deserialize;
 use rocket::Responder;
 #[cfg(test)]
 use rocket::Route;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::SqlitePool;
 
 #[cfg(test)]
@@ -47,6 +51,59 @@ impl_from_error!(sqlx::Error, "Postgres error");
 impl_from_error!(rocket::serde::json::serde_json::Error, "JSON error");
 impl_from_error!(git2::Error, "Git error");
 impl_from_error!(reqwest::Error, "Http client error");
+impl_from_error!(rocket::tokio::task::JoinError, "Background task failed");
+
+/// An error caused by the client sending bad input
+#[derive(Responder, Debug)]
+#[response(status = 400)]
+pub struct InputError {
+    pub message: &'static str,
+}
+
+impl std::fmt::Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl From<Error> for InputError {
+    fn from(error: Error) -> Self {
+        Self {
+            message: error.message,
+        }
+    }
+}
+
+impl From<std::num::ParseIntError> for InputError {
+    fn from(error: std::num::ParseIntError) -> Self {
+        Error::from(error).into()
+    }
+}
+
+/// Either kind of error, for handlers that can fail because of the client or the server
+#[derive(Responder, Debug)]
+pub enum ApiError {
+    Input(InputError),
+    #[response(status = 401)]
+    Unauthorized(&'static str),
+    #[response(status = 404)]
+    NotFound(&'static str),
+    #[response(status = 409)]
+    Conflict(&'static str),
+    Server(Error),
+}
+
+impl From<InputError> for ApiError {
+    fn from(error: InputError) -> Self {
+        Self::Input(error)
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        Self::Server(error)
+    }
+}
 
 impl<T> From<std::sync::PoisonError<T>> for Error {
     fn from(value: std::sync::PoisonError<T>) -> Self {
@@ -62,3 +119,43 @@ impl<T> From<std::sync::PoisonError<T>> for Error {
 pub struct DB {
     pub pool: SqlitePool,
 }
+
+/// Opens the SQLite database file at `path`, creating it if it doesn't exist yet
+pub async fn open_database(path: &str) -> Result<SqlitePool, sqlx::Error> {
+    SqlitePoolOptions::new()
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true),
+        )
+        .await
+}
+
+/// Reads a `T` from the `key` table of the Rocket config, or the default if the table is missing.
+/// Logs and returns `None` if the table is invalid.
+pub fn read_config<T>(figment: &Figment, key: &str) -> Option<T>
+where
+    T: for<'de> Deserialize<'de> + Default,
+{
+    if !figment.contains(key) {
+        return Some(T::default());
+    }
+    figment
+        .extract_inner(key)
+        .map_err(|e| rocket::error!("Invalid `{key}` config: {e}"))
+        .ok()
+}
+
+/// Manages a `T` read from the `key` table of the Rocket config, or the default if the table is
+/// missing
+pub fn config_fairing<T>(name: &'static str, key: &'static str) -> AdHoc
+where
+    T: for<'de> Deserialize<'de> + Default + Send + Sync + 'static,
+{
+    AdHoc::try_on_ignite(name, move |rocket| async move {
+        match read_config::<T>(rocket.figment(), key) {
+            Some(config) => Ok(rocket.manage(config)),
+            None => Err(rocket),
+        }
+    })
+}
@@ -1,20 +1,368 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::env;
+use std::fs;
+use std::io::{self, Cursor, SeekFrom};
 use std::ops::Add;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use image::io::Reader;
-use image::DynamicImage;
-use rocket::form::Form;
-use rocket::fs::{relative, NamedFile, TempFile};
-use rocket::{get, post, routes, FromForm, Route};
+use chrono::{DateTime, Utc};
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageError, ImageOutputFormat, Luma, Pixel, Primitive, Rgba};
+use rocket::fairing::AdHoc;
+use rocket::form::{self, Form, ValueField};
+use rocket::fs::{relative, TempFile};
+use rocket::http::{ContentType, Header, RawStr, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::fs::File;
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt, Take};
+use rocket::tokio::task::spawn_blocking;
+use rocket::{get, post, routes, FromForm, FromFormField, Request, Response, Route, State};
+use ulid::Ulid;
 
-use crate::common::Error;
+use crate::common::{config_fairing, ApiError, Error, InputError};
+
+/// How `/11/assets` serves its files
+#[derive(Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AssetsConfig {
+    root: PathBuf,
+    cache_control: String,
+    /// Serve a directory's `index.html` when the directory itself is asked for
+    directory_index: bool,
+    /// List a directory's contents when it has no index to serve
+    directory_listing: bool,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from(relative!("assets")),
+            cache_control: "public, max-age=3600".into(),
+            directory_index: false,
+            directory_listing: false,
+        }
+    }
+}
+
+impl AssetsConfig {
+    pub fn fairing() -> AdHoc {
+        config_fairing::<Self>("Assets", "assets")
+    }
+}
+
+/// The request headers that decide what an asset response looks like
+struct AssetRequest<'r> {
+    if_none_match: Option<&'r str>,
+    if_modified_since: Option<&'r str>,
+    range: Option<&'r str>,
+    accept_encoding: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AssetRequest<'r> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        request::Outcome::Success(Self {
+            if_none_match: headers.get_one("If-None-Match"),
+            if_modified_since: headers.get_one("If-Modified-Since"),
+            range: headers.get_one("Range"),
+            accept_encoding: headers.get_one("Accept-Encoding"),
+        })
+    }
+}
+
+impl AssetRequest<'_> {
+    /// Whether the client takes `encoding`, either by name or through `*`. Any zero q-value
+    /// is a refusal, and so is one we can't read.
+    fn accepts(&self, encoding: &str) -> bool {
+        let Some(accepted) = self.accept_encoding else {
+            return false;
+        };
+
+        let mut wildcard = None;
+        for part in accepted.split(',') {
+            let mut params = part.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let quality = params
+                .find_map(|p| {
+                    p.get(..2)
+                        .filter(|key| key.eq_ignore_ascii_case("q="))
+                        .map(|_| p[2..].trim().parse::<f64>().unwrap_or(0.0))
+                })
+                .unwrap_or(1.0);
+
+            if name.eq_ignore_ascii_case(encoding) {
+                return quality > 0.0;
+            }
+            if name == "*" {
+                wildcard = Some(quality);
+            }
+        }
+        wildcard.is_some_and(|quality| quality > 0.0)
+    }
+
+    fn is_fresh(&self, etag: &str, modified: DateTime<Utc>) -> bool {
+        // If-None-Match takes precedence when both are sent
+        if let Some(tags) = self.if_none_match {
+            return tags
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag);
+        }
+
+        self.if_modified_since
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .is_some_and(|since| modified.timestamp() <= since.timestamp())
+    }
+}
+
+enum ByteRange {
+    /// No usable range, send the whole thing
+    Full,
+    Partial {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Only single ranges are supported, anything fancier gets the full body
+    fn parse(header: Option<&str>, len: u64) -> Self {
+        let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return Self::Full;
+        };
+        let Some((start, end)) = spec.split_once('-') else {
+            return Self::Full;
+        };
+        if spec.contains(',') {
+            return Self::Full;
+        }
+
+        let range = match (start.trim().parse::<u64>(), end.trim().parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            (Ok(start), Err(_)) if end.trim().is_empty() => (start, len.saturating_sub(1)),
+            (Err(_), Ok(suffix)) if start.trim().is_empty() && suffix > 0 => {
+                (len.saturating_sub(suffix), len.saturating_sub(1))
+            }
+            _ => return Self::Full,
+        };
+
+        match range {
+            (start, end) if start < len && start <= end => Self::Partial { start, end },
+            _ => Self::Unsatisfiable,
+        }
+    }
+}
+
+enum AssetBody {
+    Empty,
+    File(File, u64),
+    /// Part of a file, already positioned and cut down to the bytes being sent
+    Range(Take<File>, u64),
+    Html(String),
+}
+
+struct AssetResponse {
+    status: Status,
+    content_type: Option<ContentType>,
+    headers: Vec<Header<'static>>,
+    body: AssetBody,
+}
+
+impl<'r> Responder<'r, 'static> for AssetResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(self.status);
+        if let Some(content_type) = self.content_type {
+            response.header(content_type);
+        }
+        for header in self.headers {
+            response.header(header);
+        }
+        match self.body {
+            AssetBody::Empty => {}
+            AssetBody::File(file, len) => {
+                response.sized_body(usize::try_from(len).ok(), file);
+            }
+            AssetBody::Range(part, len) => {
+                response.raw_header("Content-Length", len.to_string());
+                response.streamed_body(part);
+            }
+            AssetBody::Html(html) => {
+                response.header(ContentType::HTML);
+                response.sized_body(html.len(), Cursor::new(html));
+            }
+        }
+        response.ok()
+    }
+}
+
+/// Precompressed variants we look for next to a file, best first
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+async fn serve_file(
+    path: &Path,
+    config: &AssetsConfig,
+    req: &AssetRequest<'_>,
+) -> io::Result<AssetResponse> {
+    let content_type = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ContentType::from_extension);
+
+    let mut encoding = None;
+    let mut file_path = path.to_path_buf();
+    for (name, ext) in PRECOMPRESSED {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(ext);
+        let variant = PathBuf::from(variant);
+        if req.accepts(name)
+            && tokio::fs::metadata(&variant)
+                .await
+                .is_ok_and(|m| m.is_file())
+        {
+            encoding = Some(name);
+            file_path = variant;
+            break;
+        }
+    }
+
+    let mut file = File::open(&file_path).await?;
+    let metadata = file.metadata().await?;
+    let len = metadata.len();
+    let modified: DateTime<Utc> = metadata.modified()?.into();
+    let etag = format!(
+        "\"{:x}-{:x}{}\"",
+        len,
+        modified.timestamp_nanos_opt().unwrap_or_default(),
+        encoding.map(|e| format!("-{e}")).unwrap_or_default()
+    );
+
+    let mut headers = vec![
+        Header::new("ETag", etag.clone()),
+        Header::new(
+            "Last-Modified",
+            modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        ),
+        Header::new("Cache-Control", config.cache_control.clone()),
+        Header::new("Vary", "Accept-Encoding"),
+        Header::new("Accept-Ranges", "bytes"),
+    ];
+    if let Some(encoding) = encoding {
+        headers.push(Header::new("Content-Encoding", encoding));
+    }
+
+    if req.is_fresh(&etag, modified) {
+        return Ok(AssetResponse {
+            status: Status::NotModified,
+            content_type: None,
+            headers,
+            body: AssetBody::Empty,
+        });
+    }
+
+    let (status, body) = match ByteRange::parse(req.range, len) {
+        ByteRange::Full => (Status::Ok, AssetBody::File(file, len)),
+        ByteRange::Partial { start, end } => {
+            file.seek(SeekFrom::Start(start)).await?;
+            let range_len = end - start + 1;
+            headers.push(Header::new(
+                "Content-Range",
+                format!("bytes {start}-{end}/{len}"),
+            ));
+            (
+                Status::PartialContent,
+                AssetBody::Range(file.take(range_len), range_len),
+            )
+        }
+        ByteRange::Unsatisfiable => {
+            headers.push(Header::new("Content-Range", format!("bytes */{len}")));
+            (Status::RangeNotSatisfiable, AssetBody::Empty)
+        }
+    };
+
+    Ok(AssetResponse {
+        status,
+        content_type,
+        headers,
+        body,
+    })
+}
+
+async fn list_directory(dir: &Path, uri_path: &Path) -> io::Result<AssetResponse> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type().await?.is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    let base: String = uri_path
+        .iter()
+        .map(|segment| {
+            format!(
+                "{}/",
+                RawStr::new(&segment.to_string_lossy()).percent_encode()
+            )
+        })
+        .collect();
+    let items: String = names
+        .iter()
+        .map(|name| {
+            format!(
+                "<li><a href=\"/11/assets/{base}{}\">{}</a></li>",
+                RawStr::new(name.trim_end_matches('/')).percent_encode(),
+                RawStr::new(name).html_escape()
+            )
+        })
+        .collect();
+    let title = RawStr::new(&format!("/{base}")).html_escape().into_owned();
+
+    Ok(AssetResponse {
+        status: Status::Ok,
+        content_type: None,
+        headers: vec![Header::new("Cache-Control", "no-cache")],
+        body: AssetBody::Html(format!(
+            "<html><head><title>{title}</title></head><body><h1>{title}</h1><ul>{items}</ul></body></html>"
+        )),
+    })
+}
 
 #[get("/11/assets/<path..>")]
-async fn assets(path: PathBuf) -> Option<NamedFile> {
-    let path = Path::new(relative!("assets")).join(path);
+async fn assets(
+    path: PathBuf,
+    config: &State<AssetsConfig>,
+    req: AssetRequest<'_>,
+) -> Option<AssetResponse> {
+    let full_path = config.root.join(&path);
+    let metadata = tokio::fs::metadata(&full_path).await.ok()?;
+
+    if metadata.is_dir() {
+        let index = full_path.join("index.html");
+        if config.directory_index && tokio::fs::metadata(&index).await.is_ok_and(|m| m.is_file()) {
+            return serve_file(&index, config, &req).await.ok();
+        }
+        if config.directory_listing {
+            return list_directory(&full_path, &path).await.ok();
+        }
+        return None;
+    }
 
-    NamedFile::open(path).await.ok()
+    serve_file(&full_path, config, &req).await.ok()
 }
 
 #[derive(FromForm)]
@@ -22,46 +370,1086 @@ struct Image<'f> {
     image: TempFile<'f>,
 }
 
-#[post("/11/red_pixels", data = "<image>")]
-async fn count_red_pixels(mut image: Form<Image<'_>>) -> Result<String, Error> {
-    let name = image.image.name().unwrap_or("some-image.png");
-    let path = env::temp_dir().join(name);
-    image.image.persist_to(path).await?;
+/// Bounds on the images we're willing to decode, so a small upload can't expand into
+/// gigabytes of pixels
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ImageLimits {
+    max_width: u32,
+    max_height: u32,
+    /// Bytes
+    max_alloc: u64,
+}
 
-    let img = Reader::open(image.image.path().ok_or(Error {
-        message: "Temp file had no path",
-    })?)?
-    .with_guessed_format()?
-    .decode()?;
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 4096,
+            max_height: 4096,
+            max_alloc: 256 * 1024 * 1024,
+        }
+    }
+}
 
+impl ImageLimits {
+    /// Reads the limits from the `image_limits` config table, if there is one
+    pub fn fairing() -> AdHoc {
+        config_fairing::<Self>("Image limits", "image_limits")
+    }
+
+    fn check(self, width: u32, height: u32) -> Result<(), InputError> {
+        let bytes = u64::from(width) * u64::from(height) * 4;
+        if width > self.max_width || height > self.max_height || bytes > self.max_alloc {
+            return Err(InputError {
+                message: "Image exceeds the size limits",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl From<ImageLimits> for Limits {
+    fn from(limits: ImageLimits) -> Self {
+        let mut image_limits = Limits::default();
+        image_limits.max_image_width = Some(limits.max_width);
+        image_limits.max_image_height = Some(limits.max_height);
+        image_limits.max_alloc = Some(limits.max_alloc);
+        image_limits
+    }
+}
+
+/// An upload saved under a name of its own, deleted again once it's been read
+struct SavedUpload {
+    path: PathBuf,
+}
+
+impl SavedUpload {
+    async fn save(upload: &mut TempFile<'_>) -> Result<Self, Error> {
+        let path = env::temp_dir().join(format!("cch23-upload-{}", Ulid::new()));
+        upload.persist_to(&path).await?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SavedUpload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Saves the upload somewhere `image` can read it from and decodes it within the limits
+async fn decode(upload: &mut TempFile<'_>, limits: ImageLimits) -> Result<DynamicImage, ApiError> {
+    let saved = SavedUpload::save(upload).await?;
+    let path = saved.path.clone();
+
+    // Decoding an image up to the limits takes a while, so keep it off the async workers
+    spawn_blocking(move || {
+        let mut reader = Reader::open(path)
+            .map_err(Error::from)?
+            .with_guessed_format()
+            .map_err(Error::from)?;
+        reader.limits(limits.into());
+
+        match reader.decode() {
+            Ok(img) => Ok(img),
+            Err(ImageError::Limits(_)) => Err(InputError {
+                message: "Image exceeds the size limits",
+            }
+            .into()),
+            Err(e) => Err(Error::from(e).into()),
+        }
+    })
+    .await
+    .map_err(Error::from)?
+}
+
+/// What to do with the alpha channel when looking at a pixel's colour
+#[derive(FromFormField, Clone, Copy, Default)]
+enum AlphaPolicy {
+    /// Use the colour as is, however transparent it is
+    #[default]
+    Ignore,
+    /// Scale the colour by its alpha, as if over black
+    Premultiply,
+    /// Leave fully transparent pixels out
+    Skip,
+}
+
+/// Scales a channel by its alpha, as if over black, rounding to the nearest value. Red pixel
+/// counts and image analysis both go through this, so they agree on every pixel.
+fn premultiply<T>(channel: T, alpha: T) -> T
+where
+    T: Primitive + Into<u32> + TryFrom<u32>,
+{
+    let max: u32 = T::DEFAULT_MAX_VALUE.into();
+    // At most the original channel, so it always fits
+    T::try_from((channel.into() * alpha.into() + max / 2) / max).unwrap_or(T::DEFAULT_MAX_VALUE)
+}
+
+/// Counts pixels whose red outweighs green and blue together. 8 bit images are looked at as
+/// they are, like `/11/analyze` does, other integer images are widened to 16 bits per channel
+/// and float images kept as floats, so no precision is lost either way.
+fn red_pixel_count(img: &DynamicImage, alpha: AlphaPolicy) -> usize {
     macro_rules! count_pixels {
-        ($rgb_image:ident) => {
-            count_pixels!($rgb_image, saturating_add)
-        };
-        ($rgb_image:ident, $add:ident) => {{
-            let red_pxl_count = $rgb_image
+        ($rgba_image:expr, $add:ident, $transparent:expr, $premultiply:expr) => {
+            $rgba_image
                 .pixels()
-                .filter(|p| {
-                    let [red, green, blue] = p.0;
-                    red > green.$add(blue)
+                .filter_map(|p| {
+                    let [red, green, blue, a] = p.0;
+                    match alpha {
+                        AlphaPolicy::Skip if a == $transparent => None,
+                        AlphaPolicy::Premultiply => {
+                            Some([red, green, blue].map(|c| $premultiply(c, a)))
+                        }
+                        _ => Some([red, green, blue]),
+                    }
                 })
-                .count();
-
-            Ok(red_pxl_count.to_string())
-        }};
+                .filter(|[red, green, blue]| *red > green.$add(*blue))
+                .count()
+        };
     }
 
     match img {
-        DynamicImage::ImageRgb8(rgb_image) => count_pixels!(rgb_image),
-        DynamicImage::ImageRgb16(rgb_image) => count_pixels!(rgb_image),
-        DynamicImage::ImageRgb32F(rgb_image) => count_pixels!(rgb_image, add),
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            count_pixels!(img.to_rgba32f(), add, 0.0, |c: f32, a: f32| c * a)
+        }
+        DynamicImage::ImageLuma8(_)
+        | DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgb8(_)
+        | DynamicImage::ImageRgba8(_) => {
+            count_pixels!(img.to_rgba8(), saturating_add, 0, premultiply::<u8>)
+        }
+        _ => count_pixels!(img.to_rgba16(), saturating_add, 0, premultiply::<u16>),
+    }
+}
+
+#[post("/11/red_pixels?<alpha>", data = "<image>")]
+async fn count_red_pixels(
+    limits: &State<ImageLimits>,
+    mut image: Form<Image<'_>>,
+    alpha: Option<AlphaPolicy>,
+) -> Result<String, ApiError> {
+    let img = decode(&mut image.image, **limits).await?;
+
+    let count = spawn_blocking(move || red_pixel_count(&img, alpha.unwrap_or_default()))
+        .await
+        .map_err(Error::from)?;
+    Ok(count.to_string())
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+struct Colour {
+    red: u8,
+    green: u8,
+    blue: u8,
+}
+
+impl Colour {
+    fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+
+    /// Hue in degrees, saturation and value both from 0 to 1
+    fn hsv(self) -> (f64, f64, f64) {
+        let [r, g, b] = [self.red, self.green, self.blue].map(|c| f64::from(c) / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+        (hue, saturation, max)
+    }
+
+    fn into_rgb(self) -> [f64; 3] {
+        [self.red, self.green, self.blue].map(f64::from)
+    }
+
+    fn distance_sq(self, other: [f64; 3]) -> f64 {
+        self.into_rgb()
+            .iter()
+            .zip(other)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum()
+    }
+}
+
+impl From<[f64; 3]> for Colour {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from(rgb: [f64; 3]) -> Self {
+        let [red, green, blue] = rgb.map(|c| c.round().clamp(0.0, 255.0) as u8);
+        Self { red, green, blue }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct HexColour {
+    #[serde(flatten)]
+    colour: Colour,
+    hex: String,
+}
+
+impl From<Colour> for HexColour {
+    fn from(colour: Colour) -> Self {
+        Self {
+            hex: colour.hex(),
+            colour,
+        }
+    }
+}
+
+/// A named region of HSV space. A `hue_min` above `hue_max` wraps around through red.
+#[derive(FromForm)]
+struct HsvRange {
+    name: String,
+    #[field(default = 0.0)]
+    hue_min: f64,
+    #[field(default = 360.0)]
+    hue_max: f64,
+    #[field(default = 0.0)]
+    saturation_min: f64,
+    #[field(default = 1.0)]
+    saturation_max: f64,
+    #[field(default = 0.0)]
+    value_min: f64,
+    #[field(default = 1.0)]
+    value_max: f64,
+}
+
+impl HsvRange {
+    fn validate(&self) -> Result<(), InputError> {
+        let within = |min: f64, max: f64, limit: f64| {
+            (0.0..=limit).contains(&min) && (0.0..=limit).contains(&max)
+        };
+        if !within(self.hue_min, self.hue_max, 360.0) {
+            return Err(InputError {
+                message: "Hue bounds must be between 0 and 360",
+            });
+        }
+        if !within(self.saturation_min, self.saturation_max, 1.0)
+            || !within(self.value_min, self.value_max, 1.0)
+        {
+            return Err(InputError {
+                message: "Saturation and value bounds must be between 0 and 1",
+            });
+        }
+        if self.saturation_min > self.saturation_max || self.value_min > self.value_max {
+            return Err(InputError {
+                message: "Saturation and value minimums can't be above their maximums",
+            });
+        }
+        Ok(())
+    }
+
+    fn contains(&self, colour: Colour) -> bool {
+        let (hue, saturation, value) = colour.hsv();
+        let hue_matches = if self.hue_min <= self.hue_max {
+            (self.hue_min..=self.hue_max).contains(&hue)
+        } else {
+            hue >= self.hue_min || hue <= self.hue_max
+        };
+
+        hue_matches
+            && (self.saturation_min..=self.saturation_max).contains(&saturation)
+            && (self.value_min..=self.value_max).contains(&value)
+    }
+}
+
+/// Names of the counts from `dominant_channels`
+const BUILT_IN_COUNTS: [&str; 3] = ["red", "green", "blue"];
+
+/// The built in predicates: a channel that outweighs the other two put together
+fn dominant_channels(colour: Colour) -> [(&'static str, bool); 3] {
+    let Colour { red, green, blue } = colour;
+    [
+        ("red", red > green.saturating_add(blue)),
+        ("green", green > red.saturating_add(blue)),
+        ("blue", blue > red.saturating_add(green)),
+    ]
+}
+
+#[derive(FromForm)]
+struct Analysis<'f> {
+    image: TempFile<'f>,
+    #[field(default = 5)]
+    palette: usize,
+    ranges: Vec<HsvRange>,
+    alpha: Option<AlphaPolicy>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Histograms {
+    red: Vec<u64>,
+    green: Vec<u64>,
+    blue: Vec<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PaletteEntry {
+    #[serde(flatten)]
+    colour: HexColour,
+    /// Fraction of the image closest to this colour
+    share: f64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ImageStats {
+    width: u32,
+    height: u32,
+    histogram: Histograms,
+    average: Option<HexColour>,
+    palette: Vec<PaletteEntry>,
+    counts: HashMap<String, usize>,
+}
+
+const MAX_PALETTE: usize = 16;
+/// Pixels sampled for the palette, so large images don't make k-means crawl
+const PALETTE_SAMPLES: usize = 10_000;
+const PALETTE_ITERATIONS: usize = 20;
+
+/// Groups the colours into `k` clusters with k-means. Starting centroids are picked by
+/// maximin so the palette comes out the same every time.
+#[allow(clippy::cast_precision_loss)]
+fn palette(colours: &[Colour], k: usize) -> Vec<PaletteEntry> {
+    let step = (colours.len() / PALETTE_SAMPLES).max(1);
+    let samples: Vec<_> = colours.iter().step_by(step).copied().collect();
+    if samples.is_empty() {
+        return Vec::new();
+    }
+
+    let mean = |cluster: &[Colour]| {
+        let sum = cluster.iter().fold([0.0; 3], |[r, g, b], c| {
+            [
+                r + f64::from(c.red),
+                g + f64::from(c.green),
+                b + f64::from(c.blue),
+            ]
+        });
+        sum.map(|c| c / cluster.len() as f64)
+    };
+    let nearest = |centroids: &[[f64; 3]], colour: Colour| {
+        (0..centroids.len())
+            .min_by(|&a, &b| {
+                colour
+                    .distance_sq(centroids[a])
+                    .total_cmp(&colour.distance_sq(centroids[b]))
+            })
+            .unwrap_or(0)
+    };
+
+    let mut centroids = vec![mean(&samples)];
+    while centroids.len() < k {
+        let farthest = samples.iter().copied().max_by(|a, b| {
+            let da = centroids
+                .iter()
+                .map(|c| a.distance_sq(*c))
+                .fold(f64::MAX, f64::min);
+            let db = centroids
+                .iter()
+                .map(|c| b.distance_sq(*c))
+                .fold(f64::MAX, f64::min);
+            da.total_cmp(&db)
+        });
+        match farthest {
+            Some(colour) if !centroids.contains(&colour.into_rgb()) => {
+                centroids.push(colour.into_rgb());
+            }
+            // Fewer distinct colours than clusters
+            _ => break,
+        }
+    }
+
+    let mut assignments = vec![0; samples.len()];
+    for _ in 0..PALETTE_ITERATIONS {
+        let next: Vec<_> = samples.iter().map(|c| nearest(&centroids, *c)).collect();
+        let converged = next == assignments;
+        assignments = next;
+
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let cluster: Vec<_> = samples
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, a)| **a == i)
+                .map(|(c, _)| *c)
+                .collect();
+            if !cluster.is_empty() {
+                *centroid = mean(&cluster);
+            }
+        }
+
+        if converged {
+            break;
+        }
+    }
+
+    let mut palette: Vec<_> = centroids
+        .iter()
+        .enumerate()
+        .map(|(i, centroid)| PaletteEntry {
+            colour: Colour::from(*centroid).into(),
+            share: assignments.iter().filter(|a| **a == i).count() as f64 / samples.len() as f64,
+        })
+        .filter(|entry| entry.share > 0.0)
+        .collect();
+    palette.sort_by(|a, b| b.share.total_cmp(&a.share));
+    palette
+}
+
+/// Every pixel's colour, as seen through the alpha policy
+fn colours(img: &DynamicImage, alpha: AlphaPolicy) -> Vec<Colour> {
+    img.to_rgba8()
+        .pixels()
+        .filter_map(|p| {
+            let [red, green, blue, a] = p.0;
+            match alpha {
+                AlphaPolicy::Skip if a == 0 => None,
+                AlphaPolicy::Premultiply => Some(Colour {
+                    red: premultiply(red, a),
+                    green: premultiply(green, a),
+                    blue: premultiply(blue, a),
+                }),
+                _ => Some(Colour { red, green, blue }),
+            }
+        })
+        .collect()
+}
+
+fn analyze(
+    img: &DynamicImage,
+    alpha: AlphaPolicy,
+    palette_size: usize,
+    ranges: &[HsvRange],
+) -> ImageStats {
+    let colours = colours(img, alpha);
+
+    let mut histogram = Histograms {
+        red: vec![0; 256],
+        green: vec![0; 256],
+        blue: vec![0; 256],
+    };
+    let mut counts: HashMap<String, usize> = BUILT_IN_COUNTS
+        .into_iter()
+        .chain(ranges.iter().map(|r| r.name.as_str()))
+        .map(|name| (name.to_string(), 0))
+        .collect();
+    let mut sum = [0u64; 3];
+
+    for colour in &colours {
+        histogram.red[usize::from(colour.red)] += 1;
+        histogram.green[usize::from(colour.green)] += 1;
+        histogram.blue[usize::from(colour.blue)] += 1;
+        sum[0] += u64::from(colour.red);
+        sum[1] += u64::from(colour.green);
+        sum[2] += u64::from(colour.blue);
+
+        let matches = dominant_channels(*colour).into_iter().chain(
+            ranges
+                .iter()
+                .map(|r| (r.name.as_str(), r.contains(*colour))),
+        );
+        for (name, matched) in matches {
+            if matched {
+                *counts.entry(name.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let average = (!colours.is_empty())
+        .then(|| Colour::from(sum.map(|c| c as f64 / colours.len() as f64)).into());
+
+    ImageStats {
+        width: img.width(),
+        height: img.height(),
+        histogram,
+        average,
+        palette: palette(&colours, palette_size),
+        counts,
+    }
+}
+
+#[post("/11/analyze", data = "<analysis>")]
+async fn analyze_image(
+    limits: &State<ImageLimits>,
+    mut analysis: Form<Analysis<'_>>,
+) -> Result<Json<ImageStats>, ApiError> {
+    if !(1..=MAX_PALETTE).contains(&analysis.palette) {
+        return Err(InputError {
+            message: "Palette size must be between 1 and 16",
+        }
+        .into());
+    }
+    for (i, range) in analysis.ranges.iter().enumerate() {
+        range.validate()?;
+        // Counts are keyed by name, so a clash would quietly add two counts together
+        if BUILT_IN_COUNTS.contains(&range.name.as_str())
+            || analysis.ranges[..i].iter().any(|r| r.name == range.name)
+        {
+            return Err(InputError {
+                message: "Range names must be unique and can't be red, green or blue",
+            }
+            .into());
+        }
+    }
+
+    let img = decode(&mut analysis.image, **limits).await?;
+
+    // Clustering goes over every pixel, so it runs off the async workers too
+    let alpha = analysis.alpha.unwrap_or_default();
+    let palette = analysis.palette;
+    let ranges = std::mem::take(&mut analysis.ranges);
+    let stats = spawn_blocking(move || analyze(&img, alpha, palette, &ranges))
+        .await
+        .map_err(Error::from)?;
+    Ok(Json(stats))
+}
+
+/// A step in an image transformation, written in the query as e.g. `op=resize:64x64`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    /// Keep the red pixels and fade everything else to grey
+    Highlight,
+    /// White where the pixel is red, black elsewhere
+    Mask,
+    Grayscale,
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Rotate(u32),
+}
+
+/// Largest side a resize can produce, whatever the configured image limits are
+const MAX_RESIZE: u32 = 8192;
+
+impl FromStr for Operation {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, args) = s.split_once(':').unwrap_or((s, ""));
+        let numbers = |sep: char| -> Result<Vec<u32>, Self::Err> {
+            args.split(sep)
+                .map(|n| {
+                    n.trim()
+                        .parse()
+                        .map_err(|_| "Operation arguments must be integers")
+                })
+                .collect()
+        };
+
+        match (name, numbers('x'), numbers(',')) {
+            ("highlight", ..) => Ok(Self::Highlight),
+            ("mask", ..) => Ok(Self::Mask),
+            ("grayscale" | "greyscale", ..) => Ok(Self::Grayscale),
+            ("resize", Ok(size), _) => match size[..] {
+                [width @ 1..=MAX_RESIZE, height @ 1..=MAX_RESIZE] => {
+                    Ok(Self::Resize { width, height })
+                }
+                [width, height] if width > 0 && height > 0 => {
+                    Err("Resize is limited to 8192 pixels a side")
+                }
+                _ => Err("Resize takes a non-empty size like `resize:64x64`"),
+            },
+            ("crop", _, Ok(rect)) => match rect[..] {
+                [x, y, width, height] if width > 0 && height > 0 => Ok(Self::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                _ => Err("Crop takes a non-empty rectangle like `crop:x,y,width,height`"),
+            },
+            ("rotate", Ok(degrees), _) => match degrees[..] {
+                [degrees @ (90 | 180 | 270)] => Ok(Self::Rotate(degrees)),
+                _ => Err("Rotate takes 90, 180 or 270 degrees"),
+            },
+            ("resize" | "crop" | "rotate", ..) => Err("Operation arguments must be integers"),
+            _ => Err("Unknown operation"),
+        }
+    }
+}
+
+impl<'v> form::FromFormField<'v> for Operation {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Ok(field.value.parse().map_err(form::Error::validation)?)
+    }
+}
 
-        _ => Err(Error {
-            message: "Image was not RGB",
-        }),
+impl Operation {
+    fn apply(self, img: DynamicImage, limits: ImageLimits) -> Result<DynamicImage, InputError> {
+        let recolour = |img: DynamicImage, f: fn(Rgba<u8>, bool) -> Rgba<u8>| {
+            let mut rgba = img.into_rgba8();
+            for pixel in rgba.pixels_mut() {
+                let [red, green, blue, _] = pixel.0;
+                let is_red = dominant_channels(Colour { red, green, blue })[0].1;
+                *pixel = f(*pixel, is_red);
+            }
+            DynamicImage::ImageRgba8(rgba)
+        };
+
+        Ok(match self {
+            Operation::Highlight => recolour(img, |pixel, is_red| {
+                if is_red {
+                    pixel
+                } else {
+                    let Luma([luma]) = pixel.to_luma();
+                    let faded = luma / 2 + 64;
+                    Rgba([faded, faded, faded, pixel[3]])
+                }
+            }),
+            Operation::Mask => recolour(img, |pixel, is_red| {
+                let value = if is_red { u8::MAX } else { 0 };
+                Rgba([value, value, value, pixel[3]])
+            }),
+            Operation::Grayscale => img.grayscale(),
+            Operation::Resize { width, height } => {
+                limits.check(width, height)?;
+                img.resize_exact(width, height, FilterType::Triangle)
+            }
+            Operation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let fits = |start: u32, len: u32, max: u32| {
+                    start.checked_add(len).is_some_and(|end| end <= max)
+                };
+                if !fits(x, width, img.width()) || !fits(y, height, img.height()) {
+                    return Err(InputError {
+                        message: "Crop rectangle is outside the image",
+                    });
+                }
+                img.crop_imm(x, y, width, height)
+            }
+            Operation::Rotate(90) => img.rotate90(),
+            Operation::Rotate(180) => img.rotate180(),
+            Operation::Rotate(_) => img.rotate270(),
+        })
     }
 }
 
+#[post("/11/transform?<op>", data = "<image>")]
+async fn transform_image(
+    limits: &State<ImageLimits>,
+    mut image: Form<Image<'_>>,
+    op: Vec<Operation>,
+) -> Result<(ContentType, Vec<u8>), ApiError> {
+    let img = decode(&mut image.image, **limits).await?;
+    let limits = **limits;
+
+    // Resizing can make the image far bigger than it was uploaded, and encoding goes over all of
+    // it, so both stay off the async workers
+    let png = spawn_blocking(move || -> Result<Vec<u8>, ApiError> {
+        let img = op
+            .into_iter()
+            .try_fold(img, |img, op| op.apply(img, limits))?;
+
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageOutputFormat::Png)
+            .map_err(Error::from)?;
+        Ok(png.into_inner())
+    })
+    .await
+    .map_err(Error::from)??;
+
+    Ok((ContentType::PNG, png))
+}
+
 pub fn routes() -> Vec<Route> {
-    routes![assets, count_red_pixels,]
+    routes![assets, count_red_pixels, analyze_image, transform_image]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+    use rocket::http::ContentType;
+    use rocket::local::blocking::{Client, LocalResponse};
+    use rocket::serde::json::{serde_json, Value};
+
+    use super::{AssetsConfig, ImageLimits};
+
+    const BOUNDARY: &str = "cch23-test-boundary";
+
+    fn client(limits: ImageLimits, assets: AssetsConfig) -> Client {
+        Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .manage(limits)
+                .manage(assets),
+        )
+        .unwrap()
+    }
+
+    fn png(img: &image::DynamicImage) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        img.write_to(&mut bytes, ImageOutputFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    /// A multipart form with the image and some plain text fields
+    fn form(image: &[u8], fields: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+                )
+                .bytes(),
+            );
+        }
+        body.extend(
+            format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"test.png\"\r\nContent-Type: image/png\r\n\r\n").bytes(),
+        );
+        body.extend(image);
+        body.extend(format!("\r\n--{BOUNDARY}--\r\n").bytes());
+        body
+    }
+
+    fn post_form<'c>(client: &'c Client, uri: &str, body: Vec<u8>) -> LocalResponse<'c> {
+        client
+            .post(uri.to_string())
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .body(body)
+            .dispatch()
+    }
+
+    /// Left half red, right half blue with a green bottom row
+    fn test_image() -> image::DynamicImage {
+        image::DynamicImage::ImageRgb8(RgbImage::from_fn(4, 4, |x, y| match (x, y) {
+            (_, 3) => Rgb([0, 200, 0]),
+            (0 | 1, _) => Rgb([250, 10, 10]),
+            _ => Rgb([0, 0, 255]),
+        }))
+    }
+
+    #[test]
+    fn count_red_pixels_test() {
+        let client = client(ImageLimits::default(), AssetsConfig::default());
+        let response = post_form(&client, "/11/red_pixels", form(&png(&test_image()), &[]));
+
+        assert_eq!("6", response.into_string().unwrap());
+    }
+
+    #[test]
+    fn count_red_pixels_colour_types_test() {
+        use image::{DynamicImage, GrayImage, ImageOutputFormat, Luma, Rgba, RgbaImage};
+
+        let client = client(ImageLimits::default(), AssetsConfig::default());
+        let count = |bytes: &[u8], query: &str| {
+            post_form(&client, &format!("/11/red_pixels{query}"), form(bytes, &[]))
+                .into_string()
+                .unwrap()
+        };
+
+        // Top row is transparent red, second row is half transparent red
+        let rgba = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 3, |_, y| match y {
+            0 => Rgba([255, 0, 0, 0]),
+            1 => Rgba([200, 0, 0, 128]),
+            _ => Rgba([0, 0, 255, 255]),
+        }));
+        assert_eq!("4", count(&png(&rgba), ""));
+        assert_eq!("2", count(&png(&rgba), "?alpha=skip"));
+        assert_eq!("2", count(&png(&rgba), "?alpha=premultiply"));
+
+        // Barely red and almost fully transparent, which rounds to black once premultiplied,
+        // the same as `/11/analyze` sees it
+        let faint = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([1, 0, 0, 1])));
+        assert_eq!("0", count(&png(&faint), "?alpha=premultiply"));
+        let response = post_form(
+            &client,
+            "/11/analyze",
+            form(&png(&faint), &[("alpha", "premultiply")]),
+        );
+        let stats: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(0, stats["counts"]["red"]);
+
+        let gray = DynamicImage::ImageLuma8(GrayImage::from_pixel(3, 3, Luma([255])));
+        assert_eq!("0", count(&png(&gray), ""));
+        assert_eq!("6", count(&png(&test_image().to_rgba16().into()), ""));
+
+        // Formats beyond PNG decode too, including the ones image only enables by default
+        for format in [ImageOutputFormat::Jpeg(100), ImageOutputFormat::Bmp] {
+            let mut encoded = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255, 0, 0])))
+                .write_to(&mut encoded, format)
+                .unwrap();
+            assert_eq!("64", count(&encoded.into_inner(), ""));
+        }
+    }
+
+    #[test]
+    fn analyze_image_test() {
+        use rocket::http::Status;
+
+        let client = client(ImageLimits::default(), AssetsConfig::default());
+        let response = post_form(
+            &client,
+            "/11/analyze",
+            form(
+                &png(&test_image()),
+                &[
+                    ("palette", "3"),
+                    ("ranges[0].name", "blueish"),
+                    ("ranges[0].hue_min", "200"),
+                    ("ranges[0].hue_max", "260"),
+                    ("ranges[1].name", "reddish"),
+                    ("ranges[1].hue_min", "330"),
+                    ("ranges[1].hue_max", "30"),
+                    ("ranges[1].saturation_min", "0.5"),
+                ],
+            ),
+        );
+        let stats: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+
+        assert_eq!(6, stats["counts"]["red"]);
+        assert_eq!(4, stats["counts"]["green"]);
+        assert_eq!(6, stats["counts"]["blue"]);
+        assert_eq!(6, stats["counts"]["blueish"]);
+        assert_eq!(6, stats["counts"]["reddish"]);
+        assert_eq!(6, stats["histogram"]["blue"][255]);
+        assert_eq!(10, stats["histogram"]["red"][0]);
+        assert_eq!("#5e3663", stats["average"]["hex"]);
+
+        let palette = stats["palette"].as_array().unwrap();
+        assert_eq!(3, palette.len());
+        let hexes: Vec<_> = palette.iter().map(|p| p["hex"].as_str().unwrap()).collect();
+        assert!(hexes.contains(&"#fa0a0a"), "{hexes:?}");
+        assert!(hexes.contains(&"#0000ff"), "{hexes:?}");
+        assert!(hexes.contains(&"#00c800"), "{hexes:?}");
+
+        for ranges in [
+            &[("ranges[0].name", "red")][..],
+            &[("ranges[0].name", "a"), ("ranges[1].name", "a")],
+            &[("ranges[0].name", "a"), ("ranges[0].hue_max", "400")],
+            &[
+                ("ranges[0].name", "a"),
+                ("ranges[0].saturation_min", "-0.1"),
+            ],
+            &[
+                ("ranges[0].name", "a"),
+                ("ranges[0].value_min", "0.8"),
+                ("ranges[0].value_max", "0.2"),
+            ],
+        ] {
+            let response = post_form(&client, "/11/analyze", form(&png(&test_image()), ranges));
+            assert_eq!(Status::BadRequest, response.status(), "{ranges:?}");
+        }
+    }
+
+    #[test]
+    fn transform_image_test() {
+        use image::GenericImageView;
+        use rocket::http::Status;
+
+        let client = client(ImageLimits::default(), AssetsConfig::default());
+        let transform = |query: &str| {
+            post_form(
+                &client,
+                &format!("/11/transform?{query}"),
+                form(&png(&test_image()), &[]),
+            )
+        };
+
+        let response = transform("op=crop:0,0,2,4&op=rotate:90&op=mask");
+        assert_eq!(Some(ContentType::PNG), response.content_type());
+        let img = image::load_from_memory(&response.into_bytes().unwrap()).unwrap();
+        assert_eq!((4, 2), img.dimensions());
+        // The green bottom row is now the left column
+        assert_eq!([0, 0, 0, 255], img.get_pixel(0, 0).0);
+        assert_eq!([255, 255, 255, 255], img.get_pixel(1, 0).0);
+
+        let response = transform("op=highlight&op=resize:8x8");
+        let img = image::load_from_memory(&response.into_bytes().unwrap()).unwrap();
+        assert_eq!((8, 8), img.dimensions());
+        assert_eq!([250, 10, 10, 255], img.get_pixel(0, 0).0);
+
+        let response = transform("op=grayscale");
+        let img = image::load_from_memory(&response.into_bytes().unwrap()).unwrap();
+        assert!(img.as_luma8().is_some());
+
+        for query in [
+            "op=crop:2,2,4,4",
+            "op=rotate:45",
+            "op=resize:0x10",
+            "op=sharpen",
+        ] {
+            assert_ne!(Status::Ok, transform(query).status(), "{query}");
+        }
+
+        // Resizing stays bounded even when the image limits are configured away
+        let unlimited = self::client(
+            ImageLimits {
+                max_width: u32::MAX,
+                max_height: u32::MAX,
+                max_alloc: u64::MAX,
+            },
+            AssetsConfig::default(),
+        );
+        let response = post_form(
+            &unlimited,
+            "/11/transform?op=resize:100000x100000",
+            form(&png(&test_image()), &[]),
+        );
+        assert_ne!(Status::Ok, response.status());
+    }
+
+    #[test]
+    fn image_limits_test() {
+        use rocket::http::Status;
+
+        let limits = ImageLimits {
+            max_width: 4,
+            max_height: 4,
+            max_alloc: 1024,
+        };
+        let client = client(limits, AssetsConfig::default());
+
+        let response = post_form(&client, "/11/red_pixels", form(&png(&test_image()), &[]));
+        assert_eq!("6", response.into_string().unwrap());
+
+        let big = image::DynamicImage::ImageRgb8(RgbImage::new(5, 1));
+        let response = post_form(&client, "/11/red_pixels", form(&png(&big), &[]));
+        assert_eq!(Status::BadRequest, response.status());
+
+        let response = post_form(
+            &client,
+            "/11/transform?op=resize:16x16",
+            form(&png(&test_image()), &[]),
+        );
+        assert_eq!(Status::BadRequest, response.status());
+    }
+
+    #[test]
+    fn assets_test() {
+        use rocket::http::{Header, Status};
+
+        let client = client(ImageLimits::default(), AssetsConfig::default());
+        let response = client.get("/11/assets/decoration.png").dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(Some(ContentType::PNG), response.content_type());
+        assert_eq!(Some("bytes"), response.headers().get_one("Accept-Ranges"));
+        assert_eq!(
+            Some("public, max-age=3600"),
+            response.headers().get_one("Cache-Control")
+        );
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        let last_modified = response
+            .headers()
+            .get_one("Last-Modified")
+            .unwrap()
+            .to_string();
+        let len = response.into_bytes().unwrap().len();
+
+        let response = client
+            .get("/11/assets/decoration.png")
+            .header(Header::new("If-None-Match", etag))
+            .dispatch();
+        assert_eq!(Status::NotModified, response.status());
+        let response = client
+            .get("/11/assets/decoration.png")
+            .header(Header::new("If-Modified-Since", last_modified))
+            .dispatch();
+        assert_eq!(Status::NotModified, response.status());
+
+        let response = client
+            .get("/11/assets/decoration.png")
+            .header(Header::new("Range", "bytes=1-3"))
+            .dispatch();
+        assert_eq!(Status::PartialContent, response.status());
+        assert_eq!(
+            Some(format!("bytes 1-3/{len}").as_str()),
+            response.headers().get_one("Content-Range")
+        );
+        assert_eq!(Some("3"), response.headers().get_one("Content-Length"));
+        assert_eq!(b"PNG".to_vec(), response.into_bytes().unwrap());
+
+        let response = client
+            .get("/11/assets/decoration.png")
+            .header(Header::new("Range", format!("bytes={len}-")))
+            .dispatch();
+        assert_eq!(Status::RangeNotSatisfiable, response.status());
+
+        // Directories are hidden unless listing is switched on
+        assert_eq!(
+            Status::NotFound,
+            client.get("/11/assets/").dispatch().status()
+        );
+
+        let root = std::env::temp_dir().join(format!("cch23-assets-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/notes.txt"), "plain").unwrap();
+        std::fs::write(root.join("docs/notes.txt.gz"), "squashed").unwrap();
+        std::fs::write(root.join("index.html"), "<p>home</p>").unwrap();
+
+        let config = AssetsConfig {
+            root: root.clone(),
+            cache_control: "no-store".into(),
+            directory_index: true,
+            directory_listing: true,
+        };
+        let client = self::client(ImageLimits::default(), config);
+
+        let response = client
+            .get("/11/assets/docs/notes.txt")
+            .header(Header::new("Accept-Encoding", "gzip, deflate"))
+            .dispatch();
+        assert_eq!(Some("gzip"), response.headers().get_one("Content-Encoding"));
+        assert_eq!(Some(ContentType::Plain), response.content_type());
+        assert_eq!("squashed", response.into_string().unwrap());
+
+        let response = client.get("/11/assets/docs/notes.txt").dispatch();
+        assert_eq!(None, response.headers().get_one("Content-Encoding"));
+        assert_eq!("plain", response.into_string().unwrap());
+
+        for (accept_encoding, compressed) in [
+            ("gzip;q=0.0", false),
+            ("gzip; q=0.000, br", false),
+            ("*;q=0.5", true),
+            ("*, gzip;q=0", false),
+            ("identity, *;q=0", false),
+            ("GZIP;Q=0.1", true),
+        ] {
+            let response = client
+                .get("/11/assets/docs/notes.txt")
+                .header(Header::new("Accept-Encoding", accept_encoding))
+                .dispatch();
+            assert_eq!(
+                compressed,
+                response.headers().get_one("Content-Encoding").is_some(),
+                "{accept_encoding}"
+            );
+        }
+
+        let response = client.get("/11/assets/").dispatch();
+        assert_eq!("<p>home</p>", response.into_string().unwrap());
+
+        let response = client.get("/11/assets/docs").dispatch();
+        let listing = response.into_string().unwrap();
+        assert!(listing.contains(r#"<a href="/11/assets/docs/notes.txt">notes.txt</a>"#));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
 }
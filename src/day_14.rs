@@ -1,7 +1,25 @@
-use rocket::serde::json::Json;
+use std::collections::{HashMap, HashSet};
+
+use ammonia::Builder;
+use chrono::Utc;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use rocket::data::{Data, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::FromParam;
+use rocket::response::content::RawHtml;
+use rocket::serde::json::{Json, Value};
 use rocket::serde::{Deserialize, Serialize};
-use rocket::{post, routes, Route};
+use rocket::{delete, get, post, put, routes, Responder, Route, State};
+use rocket_dyn_templates::handlebars::{
+    self, Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+};
 use rocket_dyn_templates::Template;
+use sqlx::{Executor, FromRow, SqlitePool};
+
+use crate::common::{read_config, ApiError, Error, InputError};
+use crate::day_15::accounts::Authenticated;
+use crate::security::CspNonce;
 
 #[derive(Deserialize, Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -9,16 +27,904 @@ struct TemplateContext {
     content: String,
 }
 
+/// A template's context with the response's CSP nonce added as `csp_nonce`
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Page<'a, T> {
+    #[serde(flatten)]
+    context: &'a T,
+    csp_nonce: &'a CspNonce,
+}
+
 #[post("/unsafe", data = "<context>")]
-fn unescaped(context: Json<TemplateContext>) -> Template {
-    Template::render("day_14/unsafe", &*context)
+fn unescaped(context: Json<TemplateContext>, csp_nonce: &CspNonce) -> Template {
+    Template::render(
+        "day_14/unsafe",
+        Page {
+            context: &*context,
+            csp_nonce,
+        },
+    )
 }
 
 #[post("/safe", data = "<context>")]
-fn escaped(context: Json<TemplateContext>) -> Template {
-    Template::render("day_14/safe", &*context)
+fn escaped(context: Json<TemplateContext>, csp_nonce: &CspNonce) -> Template {
+    Template::render(
+        "day_14/safe",
+        Page {
+            context: &*context,
+            csp_nonce,
+        },
+    )
+}
+
+#[post("/sanitized", data = "<context>")]
+fn sanitized(context: Json<TemplateContext>, csp_nonce: &CspNonce) -> Template {
+    Template::render(
+        "day_14/sanitized",
+        Page {
+            context: &*context,
+            csp_nonce,
+        },
+    )
+}
+
+/// Which HTML survives the `sanitize` helper, everything else is stripped
+#[derive(Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Sanitizer {
+    tags: HashSet<String>,
+    /// Allowed attributes for each tag
+    attributes: HashMap<String, HashSet<String>>,
+    /// Schemes allowed in URL attributes, so `javascript:` links don't make it through
+    url_schemes: HashSet<String>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        let set = |items: &[&str]| items.iter().map(ToString::to_string).collect();
+        Self {
+            tags: set(&["b", "i", "a", "img"]),
+            attributes: HashMap::from([
+                ("a".into(), set(&["href"])),
+                ("img".into(), set(&["src"])),
+            ]),
+            url_schemes: set(&["http", "https", "mailto"]),
+        }
+    }
+}
+
+/// What Markdown renders to on top of the configured allowlist
+const MARKDOWN_TAGS: [&str; 24] = [
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "em",
+    "strong",
+    "del",
+    "blockquote",
+    "ul",
+    "ol",
+    "li",
+    "pre",
+    "code",
+    "hr",
+    "br",
+    "table",
+    "thead",
+    "tbody",
+    "tr",
+    "th",
+    "td",
+];
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Tags ammonia always removes along with their content, and won't also let through
+const CONTENT_TAGS: [&str; 2] = ["script", "style"];
+
+impl Sanitizer {
+    /// Catches the combinations ammonia would panic on when cleaning, so they fail at launch
+    fn validate(&self) -> Result<(), String> {
+        let mut tags = self.tags.iter().chain(self.attributes.keys());
+        if let Some(tag) = tags.find(|tag| CONTENT_TAGS.contains(&tag.to_lowercase().as_str())) {
+            return Err(format!("`{tag}` can't be allowed"));
+        }
+        // Every link gets its `rel` from us
+        if self
+            .attributes
+            .get("a")
+            .is_some_and(|attributes| attributes.contains("rel"))
+        {
+            return Err("`rel` can't be allowed on `a`".into());
+        }
+        Ok(())
+    }
+
+    fn builder(&self) -> Builder<'_> {
+        fn strs(items: &HashSet<String>) -> HashSet<&str> {
+            items.iter().map(String::as_str).collect()
+        }
+
+        let mut builder = Builder::empty();
+        builder
+            .tags(strs(&self.tags))
+            .generic_attributes(HashSet::new())
+            .tag_attributes(
+                self.attributes
+                    .iter()
+                    .map(|(tag, attributes)| (tag.as_str(), strs(attributes)))
+                    .collect(),
+            )
+            .url_schemes(strs(&self.url_schemes))
+            .link_rel(Some("noopener noreferrer nofollow"));
+        builder
+    }
+
+    fn clean(&self, html: &str) -> String {
+        self.builder().clean(html).to_string()
+    }
+
+    /// Renders Markdown to HTML, keeping what Markdown produces and otherwise the allowlist
+    fn markdown(&self, markdown: &str) -> String {
+        let mut builder = self.builder();
+        builder
+            .add_tags(MARKDOWN_TAGS)
+            .add_tag_attributes("code", ["class"]);
+        for heading in HEADINGS {
+            builder.add_tag_attributes(heading, ["id"]);
+        }
+        builder.clean(&markdown_to_html(markdown)).to_string()
+    }
+}
+
+/// Turns heading text into a URL fragment, e.g. "Hello, World!" into "hello-world"
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "section".into()
+    } else {
+        slug.into()
+    }
+}
+
+/// Renders Markdown with tables and strikethrough, giving every heading a unique `id`
+fn markdown_to_html(markdown: &str) -> String {
+    let mut events: Vec<_> = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+    .collect();
+
+    let mut seen = HashMap::<String, usize>::new();
+    let mut heading_text = None;
+    let mut heading_start = 0;
+    for i in 0..events.len() {
+        match &events[i] {
+            Event::Start(Tag::Heading { .. }) => {
+                heading_text = Some(String::new());
+                heading_start = i;
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = &mut heading_text {
+                    heading.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let slug = slugify(&heading_text.take().unwrap_or_default());
+                let count = seen.entry(slug.clone()).or_default();
+                let anchor = if *count == 0 {
+                    slug
+                } else {
+                    format!("{slug}-{count}")
+                };
+                *count += 1;
+                if let Event::Start(Tag::Heading { id, .. }) = &mut events[heading_start] {
+                    *id = Some(anchor.into());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    html
+}
+
+/// The string a helper was called with, or `None` if it was missing or null
+fn string_param<'a>(h: &'a Helper, name: &str) -> Result<Option<&'a str>, RenderError> {
+    match h.param(0).map(|param| param.value()) {
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(RenderError::new(format!("{name} expects a string"))),
+    }
+}
+
+/// `{{sanitize html}}` writes `html` with anything outside the allowlist removed
+impl HelperDef for Sanitizer {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        if let Some(html) = string_param(h, "sanitize")? {
+            out.write(&self.clean(html))?;
+        }
+        Ok(())
+    }
+}
+
+/// `{{markdown text}}` writes `text` rendered from Markdown and sanitised
+struct MarkdownHelper(Sanitizer);
+
+impl HelperDef for MarkdownHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        if let Some(markdown) = string_param(h, "markdown")? {
+            out.write(&self.0.markdown(markdown))?;
+        }
+        Ok(())
+    }
+}
+
+fn register_helpers(handlebars: &mut Handlebars, sanitizer: &Sanitizer) {
+    handlebars.register_helper("sanitize", Box::new(sanitizer.clone()));
+    handlebars.register_helper("markdown", Box::new(MarkdownHelper(sanitizer.clone())));
+}
+
+/// The template fairing, with the helpers our templates use and their config managed
+pub fn templates() -> AdHoc {
+    AdHoc::try_on_ignite("Templates", |rocket| async move {
+        let Some(sanitizer) = read_config::<Sanitizer>(rocket.figment(), "sanitizer") else {
+            return Err(rocket);
+        };
+        if let Err(e) = sanitizer.validate() {
+            rocket::error!("Invalid `sanitizer` config: {e}");
+            return Err(rocket);
+        }
+        let helper_sanitizer = sanitizer.clone();
+        Ok(rocket
+            .manage(sanitizer)
+            .attach(Template::custom(move |engines| {
+                register_helpers(&mut engines.handlebars, &helper_sanitizer);
+            })))
+    })
+}
+
+/// Longest template source we'll store, in bytes
+const MAX_TEMPLATE_SIZE: usize = 64 * 1024;
+
+/// A template name from the path, limited to characters that are safe in URLs
+struct TemplateName<'a>(&'a str);
+
+impl<'a> FromParam<'a> for TemplateName<'a> {
+    type Error = InputError;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let valid = !param.is_empty()
+            && param.len() <= 64
+            && param
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if valid {
+            Ok(Self(param))
+        } else {
+            Err(InputError {
+                message: "Template names are 1-64 letters, digits, '-' or '_'",
+            })
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
+#[serde(crate = "rocket::serde")]
+struct TemplateSummary {
+    name: String,
+    updated_at: i64,
+}
+
+#[derive(Serialize, FromRow)]
+#[serde(crate = "rocket::serde")]
+struct StoredTemplate {
+    name: String,
+    source: String,
+    updated_at: i64,
+}
+
+/// Handlebars templates uploaded at runtime, kept in SQLite
+pub struct CardTemplates {
+    pool: SqlitePool,
+}
+
+impl CardTemplates {
+    pub async fn new(pool: SqlitePool) -> Result<Self, Error> {
+        pool.execute(include_str!("../db/schema_14.sql")).await?;
+        Ok(Self { pool })
+    }
+
+    /// Stores `source` under `name`, returning whether it replaced an existing template
+    async fn put(&self, name: &str, source: &str) -> Result<bool, Error> {
+        let now = Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let replaced =
+            sqlx::query("UPDATE card_templates SET source = $2, updated_at = $3 WHERE name = $1")
+                .bind(name)
+                .bind(source)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                > 0;
+        if !replaced {
+            sqlx::query(
+                "INSERT INTO card_templates (name, source, updated_at) VALUES ($1, $2, $3)",
+            )
+            .bind(name)
+            .bind(source)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(replaced)
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<StoredTemplate>, Error> {
+        let template =
+            sqlx::query_as("SELECT name, source, updated_at FROM card_templates WHERE name = $1")
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(template)
+    }
+
+    async fn list(&self) -> Result<Vec<TemplateSummary>, Error> {
+        let templates = sqlx::query_as("SELECT name, updated_at FROM card_templates ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(templates)
+    }
+
+    /// Removes a template, returning whether there was one to remove
+    async fn delete(&self, name: &str) -> Result<bool, Error> {
+        let result = sqlx::query("DELETE FROM card_templates WHERE name = $1")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Partials, inline ones included, can call themselves, and rendering follows them until the
+/// stack overflows, so uploads can only use plain expressions and helpers
+fn check_template(template: &handlebars::Template) -> Result<(), InputError> {
+    use handlebars::template::TemplateElement;
+
+    for element in &template.elements {
+        match element {
+            TemplateElement::PartialExpression(_)
+            | TemplateElement::PartialBlock(_)
+            | TemplateElement::DecoratorExpression(_)
+            | TemplateElement::DecoratorBlock(_) => {
+                return Err(InputError {
+                    message: "Templates can't use partials or decorators",
+                })
+            }
+            TemplateElement::HelperBlock(helper)
+            | TemplateElement::Expression(helper)
+            | TemplateElement::HtmlExpression(helper) => {
+                for inner in [&helper.template, &helper.inverse].into_iter().flatten() {
+                    check_template(inner)?;
+                }
+            }
+            TemplateElement::RawString(_) | TemplateElement::Comment(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn invalid_template(error: &handlebars::TemplateError) -> InputError {
+    if cfg!(debug_assertions) {
+        dbg!(error);
+    }
+    InputError {
+        message: "Invalid Handlebars template",
+    }
+}
+
+#[put("/templates/<name>", data = "<source>")]
+async fn upload_template(
+    _user: Authenticated,
+    name: Result<TemplateName<'_>, InputError>,
+    source: Data<'_>,
+    templates: &State<CardTemplates>,
+) -> Result<(Status, Json<StoredTemplate>), ApiError> {
+    let TemplateName(name) = name?;
+    // Read up to our own limit rather than Rocket's much smaller default one for strings
+    let source = source
+        .open(MAX_TEMPLATE_SIZE.bytes())
+        .into_string()
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidData => InputError {
+                message: "Template must be UTF-8",
+            }
+            .into(),
+            _ => ApiError::from(Error::from(e)),
+        })?;
+    if !source.is_complete() {
+        return Err(InputError {
+            message: "Template is too large",
+        }
+        .into());
+    }
+    let source = source.into_inner();
+    check_template(&handlebars::Template::compile(&source).map_err(|e| invalid_template(&e))?)?;
+
+    let replaced = templates.put(name, &source).await?;
+    let stored = templates
+        .get(name)
+        .await?
+        .ok_or(ApiError::NotFound("Unknown template"))?;
+    let status = if replaced {
+        Status::Ok
+    } else {
+        Status::Created
+    };
+    Ok((status, Json(stored)))
+}
+
+#[get("/templates")]
+async fn list_templates(
+    templates: &State<CardTemplates>,
+) -> Result<Json<Vec<TemplateSummary>>, Error> {
+    Ok(Json(templates.list().await?))
+}
+
+#[get("/templates/<name>")]
+async fn get_template(
+    name: Result<TemplateName<'_>, InputError>,
+    templates: &State<CardTemplates>,
+) -> Result<Json<StoredTemplate>, ApiError> {
+    let TemplateName(name) = name?;
+    let template = templates
+        .get(name)
+        .await?
+        .ok_or(ApiError::NotFound("Unknown template"))?;
+    Ok(Json(template))
+}
+
+#[delete("/templates/<name>")]
+async fn delete_template(
+    _user: Authenticated,
+    name: Result<TemplateName<'_>, InputError>,
+    templates: &State<CardTemplates>,
+) -> Result<Status, ApiError> {
+    let TemplateName(name) = name?;
+    if templates.delete(name).await? {
+        Ok(Status::NoContent)
+    } else {
+        Err(ApiError::NotFound("Unknown template"))
+    }
+}
+
+/// Renders the stored template `name`, with our helpers available
+async fn render_stored(
+    name: &str,
+    context: &Value,
+    csp_nonce: &CspNonce,
+    templates: &CardTemplates,
+    sanitizer: &Sanitizer,
+) -> Result<RawHtml<String>, ApiError> {
+    let template = templates
+        .get(name)
+        .await?
+        .ok_or(ApiError::NotFound("Unknown template"))?;
+
+    let mut handlebars = Handlebars::new();
+    register_helpers(&mut handlebars, sanitizer);
+    let html = handlebars
+        .render_template(&template.source, &Page { context, csp_nonce })
+        .map_err(|e| {
+            if cfg!(debug_assertions) {
+                dbg!(e);
+            }
+            InputError {
+                message: "Couldn't render template with this context",
+            }
+        })?;
+    Ok(RawHtml(html))
+}
+
+#[post("/templates/<name>/render", data = "<context>")]
+async fn render_template(
+    name: Result<TemplateName<'_>, InputError>,
+    context: Json<Value>,
+    csp_nonce: &CspNonce,
+    templates: &State<CardTemplates>,
+    sanitizer: &State<Sanitizer>,
+) -> Result<RawHtml<String>, ApiError> {
+    let TemplateName(name) = name?;
+    render_stored(name, &context, csp_nonce, templates, sanitizer).await
+}
+
+#[derive(Responder)]
+enum MarkdownPage {
+    Stored(RawHtml<String>),
+    BuiltIn(Box<Template>),
+}
+
+/// Renders the Markdown in `content` to sanitised HTML, then renders that as `content` in a
+/// stored template, or the built-in Markdown page
+#[post("/markdown?<template>", data = "<context>")]
+async fn markdown(
+    template: Option<&str>,
+    context: Json<Value>,
+    csp_nonce: &CspNonce,
+    templates: &State<CardTemplates>,
+    sanitizer: &State<Sanitizer>,
+) -> Result<MarkdownPage, ApiError> {
+    let mut context = context.into_inner();
+    let content = context.get_mut("content").ok_or(InputError {
+        message: "Missing Markdown `content`",
+    })?;
+    let html = content
+        .as_str()
+        .map(|markdown| sanitizer.markdown(markdown))
+        .ok_or(InputError {
+            message: "Markdown `content` should be a string",
+        })?;
+    *content = Value::String(html);
+
+    match template.map(TemplateName::from_param).transpose()? {
+        Some(TemplateName(name)) => Ok(MarkdownPage::Stored(
+            render_stored(name, &context, csp_nonce, templates, sanitizer).await?,
+        )),
+        None => Ok(MarkdownPage::BuiltIn(Box::new(Template::render(
+            "day_14/markdown",
+            Page {
+                context: &context,
+                csp_nonce,
+            },
+        )))),
+    }
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![unescaped, escaped]
+    routes![
+        unescaped,
+        escaped,
+        sanitized,
+        upload_template,
+        list_templates,
+        get_template,
+        delete_template,
+        render_template,
+        markdown
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::{serde_json, Value};
+    use sqlx::SqlitePool;
+
+    use super::CardTemplates;
+    use crate::day_15::accounts::Accounts;
+
+    /// A client, and the header to send to change templates
+    async fn client() -> (Client, Header<'static>) {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let templates = CardTemplates::new(pool.clone()).await.unwrap();
+        let accounts = Accounts::new(pool).await.unwrap();
+        let token = accounts.test_session("elf").await;
+        let client = Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .manage(templates)
+                .manage(accounts)
+                .attach(super::templates()),
+        )
+        .await
+        .unwrap();
+        (
+            client,
+            Header::new("Authorization", format!("Bearer {token}")),
+        )
+    }
+
+    #[rocket::async_test]
+    async fn card_templates_test() {
+        let (client, auth) = client().await;
+
+        let response = client
+            .put("/templates/card")
+            .header(auth.clone())
+            .body("<h1>{{title}}</h1>{{#each items}}<li>{{this}}</li>{{/each}}")
+            .dispatch()
+            .await;
+        assert_eq!(Status::Created, response.status());
+
+        let response = client
+            .post("/templates/card/render")
+            .header(ContentType::JSON)
+            .body(r#"{"title": "<Hi>", "items": ["a", "b"]}"#)
+            .dispatch()
+            .await;
+        assert_eq!(Some(ContentType::HTML), response.content_type());
+        assert_eq!(
+            "<h1>&lt;Hi&gt;</h1><li>a</li><li>b</li>",
+            response.into_string().await.unwrap()
+        );
+
+        let response = client
+            .put("/templates/card")
+            .header(auth.clone())
+            .body("<p>{{title}}</p>")
+            .dispatch()
+            .await;
+        assert_eq!(Status::Ok, response.status());
+
+        let response = client.get("/templates").dispatch().await;
+        let list: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!("card", list[0]["name"]);
+        assert_eq!(1, list.as_array().unwrap().len());
+
+        let response = client.get("/templates/card").dispatch().await;
+        let template: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!("<p>{{title}}</p>", template["source"]);
+
+        let response = client
+            .delete("/templates/card")
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(Status::NoContent, response.status());
+        let response = client.get("/templates/card").dispatch().await;
+        assert_eq!(Status::NotFound, response.status());
+        let response = client
+            .delete("/templates/card")
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(Status::NotFound, response.status());
+    }
+
+    #[rocket::async_test]
+    async fn invalid_templates_test() {
+        let (client, auth) = client().await;
+
+        let response = client
+            .put("/templates/broken")
+            .header(auth.clone())
+            .body("{{#if title}}unclosed")
+            .dispatch()
+            .await;
+        assert_eq!(Status::BadRequest, response.status());
+
+        // Rendering these would recurse until the stack overflows
+        for source in [
+            r#"{{#*inline "a"}}x{{> a}}{{/inline}}{{> a}}"#,
+            "{{#if title}}{{> card}}{{/if}}",
+            "{{#> layout}}body{{/layout}}",
+        ] {
+            let response = client
+                .put("/templates/recursive")
+                .header(auth.clone())
+                .body(source)
+                .dispatch()
+                .await;
+            assert_eq!(Status::BadRequest, response.status(), "{source}");
+        }
+
+        let response = client
+            .put("/templates/anonymous")
+            .body("fine")
+            .dispatch()
+            .await;
+        assert_eq!(Status::Unauthorized, response.status());
+
+        let response = client
+            .put("/templates/not%20ok")
+            .header(auth.clone())
+            .body("fine")
+            .dispatch()
+            .await;
+        assert_eq!(Status::BadRequest, response.status());
+
+        let response = client
+            .put("/templates/helper")
+            .header(auth.clone())
+            .body("{{shout title}}")
+            .dispatch()
+            .await;
+        assert_eq!(Status::Created, response.status());
+        let response = client
+            .post("/templates/helper/render")
+            .header(ContentType::JSON)
+            .body("{}")
+            .dispatch()
+            .await;
+        assert_eq!(Status::BadRequest, response.status());
+
+        // Sizes past Rocket's 8 KiB string limit are fine up to our own
+        let response = client
+            .put("/templates/long")
+            .header(auth.clone())
+            .body(format!("<p>{}</p>", "x".repeat(32 * 1024)))
+            .dispatch()
+            .await;
+        assert_eq!(Status::Created, response.status());
+        let response = client
+            .put("/templates/too-long")
+            .header(auth.clone())
+            .body("x".repeat(super::MAX_TEMPLATE_SIZE + 1))
+            .dispatch()
+            .await;
+        assert_eq!(Status::BadRequest, response.status());
+
+        let response = client.get("/templates").dispatch().await;
+        let list: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(2, list.as_array().unwrap().len());
+    }
+
+    #[rocket::async_test]
+    async fn sanitized_test() {
+        let (client, auth) = client().await;
+
+        let response = client
+            .post("/sanitized")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"content": "<b onclick=\"steal()\">bold</b> <script>alert(1)</script><a href=\"javascript:alert(1)\">x</a> <a href=\"https://example.com\">ok</a> <img src=\"cat.png\" onerror=\"steal()\"> <div>gone</div>"}"#,
+            )
+            .dispatch()
+            .await;
+        let html = response.into_string().await.unwrap();
+        assert!(html.contains(
+            r#"<b>bold</b> <a rel="noopener noreferrer nofollow">x</a> <a href="https://example.com" rel="noopener noreferrer nofollow">ok</a> <img src="cat.png"> gone"#
+        ), "{html}");
+
+        // Uploaded templates get the helper too
+        client
+            .put("/templates/rich")
+            .header(auth.clone())
+            .body("<p>{{sanitize bio}}</p>")
+            .dispatch()
+            .await;
+        let response = client
+            .post("/templates/rich/render")
+            .header(ContentType::JSON)
+            .body(r#"{"bio": "<i>hi</i><iframe src=\"x\"></iframe>"}"#)
+            .dispatch()
+            .await;
+        assert_eq!("<p><i>hi</i></p>", response.into_string().await.unwrap());
+    }
+
+    #[rocket::async_test]
+    async fn sanitizer_config_test() {
+        use rocket::error::ErrorKind;
+        use rocket::figment::Figment;
+
+        for (key, value) in [
+            ("sanitizer.tags", r#"["b", "script"]"#),
+            ("sanitizer.tags", r#"["STYLE"]"#),
+            ("sanitizer.attributes", r#"{"style": ["media"]}"#),
+            ("sanitizer.attributes", r#"{"a": ["href", "rel"]}"#),
+        ] {
+            let figment = Figment::from(rocket::Config::default())
+                .merge((key, serde_json::from_str::<Value>(value).unwrap()));
+            let ignited = rocket::custom(figment)
+                .attach(super::templates())
+                .ignite()
+                .await;
+            let error = ignited.expect_err(&format!("{key} = {value} should fail"));
+            assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+        }
+    }
+
+    #[test]
+    fn markdown_anchors_test() {
+        let html = super::markdown_to_html(
+            "# Hello, World!\n\n## Hello `world`\n\n# Hello world\n\n# ???",
+        );
+        assert_eq!(
+            "<h1 id=\"hello-world\">Hello, World!</h1>\n<h2 id=\"hello-world-1\">Hello <code>world</code></h2>\n<h1 id=\"hello-world-2\">Hello world</h1>\n<h1 id=\"section\">???</h1>\n",
+            html
+        );
+    }
+
+    #[rocket::async_test]
+    async fn markdown_test() {
+        let (client, auth) = client().await;
+        let markdown = "# Menu\n\n| Cookie | Stars |\n|---|---|\n| Ginger | 5 |\n\n```rust\nlet x = 1 < 2;\n```\n\n<script>alert(1)</script>\n\n[click](javascript:alert(1))";
+
+        let response = client
+            .post("/markdown")
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "content": markdown }).to_string())
+            .dispatch()
+            .await;
+        let html = response.into_string().await.unwrap();
+        for fragment in [
+            r#"<h1 id="menu">Menu</h1>"#,
+            "<table><thead><tr><th>Cookie</th><th>Stars</th></tr></thead><tbody>",
+            "<td>Ginger</td>",
+            r#"<pre><code class="language-rust">let x = 1 &lt; 2;"#,
+            r#"<a rel="noopener noreferrer nofollow">click</a>"#,
+        ] {
+            assert!(
+                html.contains(fragment),
+                "'{html}' should contain '{fragment}'"
+            );
+        }
+        assert!(!html.contains("script"), "{html}");
+
+        client
+            .put("/templates/post")
+            .header(auth.clone())
+            .body("<article data-title=\"{{title}}\">{{{content}}}</article><aside>{{markdown note}}</aside>")
+            .dispatch()
+            .await;
+        let response = client
+            .post("/markdown?template=post")
+            .header(ContentType::JSON)
+            .body(r#"{"title": "Hi", "content": "*hi*", "note": "**bye**"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(
+            "<article data-title=\"Hi\"><p><em>hi</em></p>\n</article><aside><p><strong>bye</strong></p>\n</aside>",
+            response.into_string().await.unwrap()
+        );
+
+        let response = client
+            .post("/markdown")
+            .header(ContentType::JSON)
+            .body(r#"{"title": "No content"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(Status::BadRequest, response.status());
+    }
+
+    #[rocket::async_test]
+    async fn csp_nonce_test() {
+        let (client, auth) = client().await;
+
+        client
+            .put("/templates/nonce")
+            .header(auth.clone())
+            .body("{{csp_nonce}}")
+            .dispatch()
+            .await;
+        let response = client
+            .post("/templates/nonce/render")
+            .header(ContentType::JSON)
+            .body("{}")
+            .dispatch()
+            .await;
+        assert_eq!(22, response.into_string().await.unwrap().len());
+    }
 }
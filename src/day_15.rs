@@ -1,10 +1,26 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
 use data_encoding::HEXLOWER;
 use ring::digest;
+use rocket::fairing::AdHoc;
 use rocket::http::Status;
-use rocket::post;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
-use rocket::{routes, Route};
+use rocket::tokio::task::spawn_blocking;
+use rocket::{get, post, put, routes, FromFormField, Route, State};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::common::{read_config, ApiError, Error, InputError};
+
+pub mod accounts;
+mod breaches;
+mod generator;
+mod strength;
+
+use accounts::Authenticated;
+pub use breaches::Breaches;
 
 /// I don't like how this came out
 /// Rocket doesn't support validation of json request bodies out of the box
@@ -18,55 +34,453 @@ struct Password {
     input: String,
 }
 
-type Validator = fn(&str) -> bool;
+/// A check a password has to pass, built from the original puzzle validators
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "rule", rename_all = "snake_case")]
+enum Rule {
+    MinLength {
+        min: usize,
+    },
+    MinVowels {
+        min: usize,
+        #[serde(default = "Rule::default_vowels")]
+        vowels: String,
+    },
+    /// The same letter twice in a row
+    RepeatedLetter,
+    ForbiddenSubstrings {
+        substrings: Vec<String>,
+    },
+    CharacterClasses {
+        #[serde(default)]
+        upper: bool,
+        #[serde(default)]
+        lower: bool,
+        #[serde(default)]
+        digit: bool,
+    },
+    MinDigits {
+        min: usize,
+    },
+    /// The runs of digits have to add up to `sum`
+    DigitSum {
+        sum: u32,
+    },
+    /// Each character of `word` appears exactly once, in order
+    OrderedOnce {
+        word: String,
+    },
+    /// Letters surrounding a different character, like "xyx"
+    Sandwich {
+        #[serde(default = "Rule::one")]
+        min: usize,
+    },
+    CharRange {
+        start: char,
+        end: char,
+    },
+    Emoji,
+    /// The hex SHA-256 of the password ends with `suffix`
+    Sha256Suffix {
+        suffix: String,
+    },
+    /// The estimated strength, from 0 to 4, is at least `score`
+    MinStrength {
+        score: u8,
+    },
+    /// Not in the breached passwords corpus
+    NotBreached,
+}
 
-fn vowel_count() -> Validator {
-    const VOWELS: &str = "aeiouy";
-    |input| {
-        let mut vowel_count = 0;
+impl Rule {
+    fn default_vowels() -> String {
+        "aeiouy".into()
+    }
 
-        for char in input.chars() {
-            if VOWELS.contains(char) {
-                vowel_count += 1;
+    fn one() -> usize {
+        1
+    }
+
+    /// Whether the parameters make sense
+    fn check(&self) -> Result<(), InputError> {
+        match self {
+            Self::CharRange { start, end } if start > end => Err(InputError {
+                message: "`char_range` start is after its end",
+            }),
+            Self::OrderedOnce { word } if word.is_empty() => Err(InputError {
+                message: "`ordered_once` needs a word",
+            }),
+            Self::ForbiddenSubstrings { substrings } if substrings.iter().any(String::is_empty) => {
+                Err(InputError {
+                    message: "`forbidden_substrings` can't contain an empty string",
+                })
+            }
+            Self::Sha256Suffix { suffix }
+                if !suffix
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) =>
+            {
+                Err(InputError {
+                    message: "`sha256_suffix` should be lowercase hex",
+                })
             }
+            Self::MinStrength { score } if *score > 4 => Err(InputError {
+                message: "`min_strength` score goes up to 4",
+            }),
+            _ => Ok(()),
         }
+    }
+
+    fn passes(&self, input: &Text, breaches: &Breaches) -> bool {
+        match self {
+            Self::MinLength { min } => input.graphemes.len() >= *min,
+            Self::MinVowels { min, vowels } => {
+                let vowels = Text::graphemes(vowels);
+                input
+                    .graphemes
+                    .iter()
+                    .filter(|g| vowels.iter().any(|v| v == *g))
+                    .count()
+                    >= *min
+            }
+            Self::RepeatedLetter => input
+                .graphemes
+                .windows(2)
+                .any(|pair| is_letter(pair[0]) && pair[0] == pair[1]),
+            Self::ForbiddenSubstrings { substrings } => !substrings.iter().any(|s| {
+                let needle = Text::graphemes(s);
+                input
+                    .graphemes
+                    .windows(needle.len())
+                    .any(|window| window == needle)
+            }),
+            Self::CharacterClasses {
+                upper,
+                lower,
+                digit,
+            } => {
+                (!upper || input.nfc.chars().any(char::is_uppercase))
+                    && (!lower || input.nfc.chars().any(char::is_lowercase))
+                    && (!digit || input.graphemes.iter().any(|g| is_digit(g)))
+            }
+            Self::MinDigits { min } => {
+                input.graphemes.iter().filter(|g| is_digit(g)).count() >= *min
+            }
+            Self::DigitSum { sum } => {
+                // A number too big to add up is too big to hit the sum
+                let total = input
+                    .graphemes
+                    .split(|g| !is_digit(g))
+                    .filter(|group| !group.is_empty())
+                    .try_fold(0u64, |total, group| {
+                        total.checked_add(group.concat().parse::<u64>().ok()?)
+                    });
+
+                total == Some(u64::from(*sum))
+            }
+            Self::OrderedOnce { word } => {
+                let word = Text::graphemes(word);
+
+                // find number of instances and check none are > 1
+                let one_of_each = word
+                    .iter()
+                    .map(|w| input.graphemes.iter().filter(|g| *g == w).count())
+                    .all(|n| n == 1);
+
+                // find index of each grapheme and make sure they are in ascending order
+                let in_order = {
+                    let indices: Vec<_> = word
+                        .iter()
+                        .map(|w| input.graphemes.iter().position(|g| g == w))
+                        .collect();
+                    indices.windows(2).all(|pair| pair[0] < pair[1])
+                };
+
+                one_of_each && in_order
+            }
+            Self::Sandwich { min } => {
+                let sandwiches = input
+                    .graphemes
+                    .windows(3)
+                    .filter(|w| is_letter(w[0]) && w[0] != w[1] && w[0] == w[2])
+                    .count();
 
-        vowel_count >= 3
+                sandwiches >= *min
+            }
+            Self::CharRange { start, end } => {
+                input.nfc.chars().any(|c| (*start..=*end).contains(&c))
+            }
+            Self::Emoji => input.graphemes.iter().any(|g| is_emoji(g)),
+            Self::Sha256Suffix { suffix } => HEXLOWER
+                .encode(digest::digest(&digest::SHA256, input.nfc.as_bytes()).as_ref())
+                .ends_with(suffix.as_str()),
+            Self::MinStrength { score } => strength::estimate(input.nfc).score >= *score,
+            Self::NotBreached => breaches.count(input.nfc) == 0,
+        }
     }
 }
 
-fn has_repeated() -> Validator {
-    |input| {
-        let mut has_repeated = false;
+/// A password in NFC, split into grapheme clusters so that what a user sees as one character
+/// counts as one, whatever the bytes or code points underneath
+struct Text<'a> {
+    nfc: &'a str,
+    graphemes: Vec<&'a str>,
+}
 
-        for (i, char) in input.char_indices() {
-            if i != 0 && char.is_alphabetic() && input.chars().nth(i - 1) == Some(char) {
-                has_repeated = true;
-            }
+impl<'a> Text<'a> {
+    fn new(nfc: &'a str) -> Self {
+        Self {
+            nfc,
+            graphemes: nfc.graphemes(true).collect(),
         }
+    }
+
+    /// The NFC grapheme clusters of a rule parameter, for comparing with a password's
+    fn graphemes(param: &str) -> Vec<String> {
+        param
+            .nfc()
+            .collect::<String>()
+            .graphemes(true)
+            .map(Into::into)
+            .collect()
+    }
+}
+
+/// A letter, possibly with combining marks, like "é" or "n\u{303}"
+fn is_letter(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_alphabetic)
+}
+
+/// A lone ASCII digit: "1" counts, but the keycap emoji "1\u{fe0f}\u{20e3}" doesn't, and
+/// neither do digits from other scripts, which `DigitSum` couldn't add up
+fn is_digit(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_digit()) && chars.next().is_none()
+}
+
+/// An emoji shown as one, either by default or asked for with the emoji variation selector
+fn is_emoji(grapheme: &str) -> bool {
+    grapheme.chars().any(unic_emoji_char::is_emoji_presentation)
+        || (grapheme
+            .chars()
+            .next()
+            .is_some_and(unic_emoji_char::is_emoji)
+            && grapheme.contains('\u{fe0f}'))
+}
+
+/// A rule with what to respond when a password breaks it
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+struct RuleSpec {
+    #[serde(flatten)]
+    rule: Rule,
+    #[serde(default = "RuleSpec::default_status")]
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl RuleSpec {
+    fn default_status() -> u16 {
+        400
+    }
+
+    fn new(rule: Rule, status: Status, reason: Option<&str>) -> Self {
+        Self {
+            rule,
+            status: status.code,
+            reason: reason.map(Into::into),
+        }
+    }
 
-        has_repeated
+    fn status(&self) -> Status {
+        Status::new(self.status)
     }
 }
 
-fn has_naughty_substring() -> Validator {
-    const NAUGHTY_SUBSTRS: [&str; 4] = ["ab", "cd", "pq", "xy"];
+/// Rules checked in order, the first one a password breaks decides the response
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+struct RuleSet {
+    rules: Vec<RuleSpec>,
+    /// Sent along with a nice result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    success_reason: Option<String>,
+}
 
-    |input| {
-        let mut has_naughty_substring = false;
+impl RuleSet {
+    fn nice() -> Self {
+        let naughty = |rule| RuleSpec::new(rule, Status::BadRequest, None);
+        Self {
+            rules: vec![
+                naughty(Rule::MinVowels {
+                    min: 3,
+                    vowels: Rule::default_vowels(),
+                }),
+                naughty(Rule::RepeatedLetter),
+                naughty(Rule::ForbiddenSubstrings {
+                    substrings: ["ab", "cd", "pq", "xy"].map(Into::into).to_vec(),
+                }),
+            ],
+            success_reason: None,
+        }
+    }
 
-        for i in 0..input.len() {
-            if i != 0 && NAUGHTY_SUBSTRS.contains(&&input[i - 1..=i]) {
-                has_naughty_substring = true;
+    fn game() -> Self {
+        Self {
+            rules: vec![
+                RuleSpec::new(
+                    Rule::MinLength { min: 8 },
+                    Status::BadRequest,
+                    Some("8 chars"),
+                ),
+                RuleSpec::new(
+                    Rule::CharacterClasses {
+                        upper: true,
+                        lower: true,
+                        digit: true,
+                    },
+                    Status::BadRequest,
+                    Some("more types of chars"),
+                ),
+                RuleSpec::new(
+                    Rule::MinDigits { min: 5 },
+                    Status::BadRequest,
+                    Some("55555"),
+                ),
+                RuleSpec::new(
+                    Rule::DigitSum { sum: 2023 },
+                    Status::BadRequest,
+                    Some("math is hard"),
+                ),
+                RuleSpec::new(
+                    Rule::OrderedOnce { word: "joy".into() },
+                    Status::NotAcceptable,
+                    Some("not joyful enough"),
+                ),
+                RuleSpec::new(
+                    Rule::Sandwich { min: 1 },
+                    Status::UnavailableForLegalReasons,
+                    Some("illegal: no sandwich"),
+                ),
+                RuleSpec::new(
+                    Rule::CharRange {
+                        start: '\u{2980}',
+                        end: '\u{2BFF}',
+                    },
+                    Status::RangeNotSatisfiable,
+                    Some("outranged"),
+                ),
+                RuleSpec::new(Rule::Emoji, Status::UpgradeRequired, Some("😳")),
+                RuleSpec::new(
+                    Rule::Sha256Suffix { suffix: "a".into() },
+                    Status::ImATeapot,
+                    Some("not a coffee brewer"),
+                ),
+            ],
+            success_reason: Some("that's a nice password".into()),
+        }
+    }
+
+    /// Checks the rules make sense, so a broken rule set is refused rather than stored
+    fn check(&self) -> Result<(), InputError> {
+        if self.rules.is_empty() {
+            return Err(InputError {
+                message: "A rule set needs at least one rule",
+            });
+        }
+        if self.rules.len() > MAX_RULES {
+            return Err(InputError {
+                message: "A rule set can have at most 32 rules",
+            });
+        }
+        for spec in &self.rules {
+            if !(400..=599).contains(&spec.status) {
+                return Err(InputError {
+                    message: "Rule statuses should be 4xx or 5xx",
+                });
             }
+            spec.rule.check()?;
+        }
+        Ok(())
+    }
+
+    fn validate(
+        &self,
+        password: &Password,
+        mode: Mode,
+        breaches: &Breaches,
+    ) -> (Status, ValidationResult) {
+        let nfc: String = password.input.nfc().collect();
+        let input = Text::new(&nfc);
+
+        if let Mode::First = mode {
+            return match self
+                .rules
+                .iter()
+                .find(|spec| !spec.rule.passes(&input, breaches))
+            {
+                Some(spec) => (
+                    spec.status(),
+                    ValidationResult::naughty(spec.reason.clone()),
+                ),
+                None => (
+                    Status::Ok,
+                    ValidationResult::nice(self.success_reason.clone()),
+                ),
+            };
         }
 
-        !has_naughty_substring
+        let (passed, failed): (Vec<_>, Vec<_>) = self
+            .rules
+            .iter()
+            .enumerate()
+            .partition(|(_, spec)| spec.rule.passes(&input, breaches));
+        let report = |(index, spec): (usize, &RuleSpec), failed: bool| RuleReport {
+            index,
+            rule: spec.rule.clone(),
+            status: failed.then_some(spec.status),
+            reason: spec.reason.clone().filter(|_| failed),
+        };
+
+        // The first failure still decides the status and reason, as it would have on its own
+        let (status, mut result) = match failed.first() {
+            Some((_, spec)) => (
+                spec.status(),
+                ValidationResult::naughty(spec.reason.clone()),
+            ),
+            None => (
+                Status::Ok,
+                ValidationResult::nice(self.success_reason.clone()),
+            ),
+        };
+        result.failures = Some(failed.into_iter().map(|r| report(r, true)).collect());
+        result.passed = Some(passed.into_iter().map(|r| report(r, false)).collect());
+        (status, result)
     }
 }
 
-fn validate_password(password: &Password, rules: &[Validator]) -> bool {
-    rules.iter().all(|r| r(&password.input))
+/// Whether validation stops at the first broken rule or checks them all
+#[derive(FromFormField, Default, Clone, Copy)]
+enum Mode {
+    #[default]
+    First,
+    All,
+}
+
+/// How one rule went, in `all` mode
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RuleReport {
+    /// Position of the rule in its set
+    index: usize,
+    #[serde(flatten)]
+    rule: Rule,
+    /// The status this rule would have responded with on its own, for failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
 }
 
 #[allow(non_camel_case_types)]
@@ -82,245 +496,851 @@ enum NiceOrNaughty {
 struct ValidationResult {
     result: NiceOrNaughty,
     #[serde(skip_serializing_if = "Option::is_none")]
-    reason: Option<&'static str>,
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option<Vec<RuleReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    passed: Option<Vec<RuleReport>>,
 }
 
 impl ValidationResult {
-    fn nice() -> Self {
+    fn nice(reason: Option<String>) -> Self {
         Self {
             result: NiceOrNaughty::nice,
-            reason: None,
+            reason,
+            failures: None,
+            passed: None,
         }
     }
 
-    fn naughty() -> Self {
+    fn naughty(reason: Option<String>) -> Self {
         Self {
             result: NiceOrNaughty::naughty,
-            reason: None,
+            reason,
+            failures: None,
+            passed: None,
         }
     }
+}
 
-    fn naughty_with_reason(reason: &'static str) -> Self {
-        Self {
-            result: NiceOrNaughty::naughty,
-            reason: Some(reason),
-        }
+/// Names that always mean the puzzle's own rules
+const BUILT_IN: [&str; 2] = ["nice", "game"];
+/// Most rule sets that can be added at runtime, on top of the built-in and configured ones
+const MAX_RULE_SETS: usize = 100;
+/// Most rules a rule set can have
+const MAX_RULES: usize = 32;
+
+/// Rule set names are kept to what reads well in a URL, so look-alikes can't sit next to the
+/// sets they imitate
+fn check_name(name: &str) -> Result<(), InputError> {
+    let valid = (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(InputError {
+            message: "Rule set names are 1 to 64 lower case letters, digits, `-` or `_`",
+        })
     }
+}
 
-    fn nice_with_reason() -> Self {
-        Self {
-            result: NiceOrNaughty::nice,
-            reason: Some("that's a nice password"),
+/// Rule sets by name: the built-in ones, those from the `rulesets` config and those added at
+/// runtime
+pub struct RuleSets {
+    sets: RwLock<HashMap<String, RuleSet>>,
+    /// The built-in and configured sets, which runtime changes leave alone
+    fixed: HashSet<String>,
+}
+
+impl RuleSets {
+    fn new(configured: HashMap<String, RuleSet>) -> Result<Self, InputError> {
+        let mut sets = HashMap::from([
+            ("nice".to_string(), RuleSet::nice()),
+            ("game".to_string(), RuleSet::game()),
+        ]);
+        for (name, set) in configured {
+            if BUILT_IN.contains(&name.as_str()) {
+                return Err(InputError {
+                    message: "Built-in rule sets can't be replaced",
+                });
+            }
+            check_name(&name)?;
+            set.check()?;
+            sets.insert(name, set);
         }
+        Ok(Self {
+            fixed: sets.keys().cloned().collect(),
+            sets: RwLock::new(sets),
+        })
+    }
+
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Rule sets", |rocket| async move {
+            let Some(configured) = read_config(rocket.figment(), "rulesets") else {
+                return Err(rocket);
+            };
+            match Self::new(configured) {
+                Ok(rule_sets) => Ok(rocket.manage(rule_sets)),
+                Err(e) => {
+                    rocket::error!("Invalid rule set in `rulesets` config: {e}");
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    fn get(&self, name: &str) -> Result<Option<RuleSet>, Error> {
+        Ok(self.sets.read()?.get(name).cloned())
     }
-}
 
-impl From<&ValidationResult> for Status {
-    fn from(value: &ValidationResult) -> Self {
-        match value.result {
-            NiceOrNaughty::nice => Status::Ok,
-            NiceOrNaughty::naughty => Status::BadRequest,
+    /// Stores a rule set, returning whether it replaced one with the same name
+    fn put(&self, name: &str, set: RuleSet) -> Result<bool, ApiError> {
+        if self.fixed.contains(name) {
+            return Err(InputError {
+                message: "Built-in and configured rule sets can't be replaced",
+            }
+            .into());
+        }
+        check_name(name)?;
+        set.check()?;
+        let mut sets = self.sets.write().map_err(Error::from)?;
+        // Replacing a set doesn't take any more room
+        if !sets.contains_key(name) && sets.len() - self.fixed.len() >= MAX_RULE_SETS {
+            return Err(InputError {
+                message: "No more rule sets can be added",
+            }
+            .into());
         }
+        let replaced = sets.insert(name.into(), set);
+        Ok(replaced.is_some())
+    }
+
+    fn names(&self) -> Result<Vec<String>, Error> {
+        let mut names: Vec<_> = self.sets.read()?.keys().cloned().collect();
+        names.sort();
+        Ok(names)
     }
 }
 
-#[post("/nice", data = "<password>")]
-fn nice(password: Json<Password>) -> (Status, Json<ValidationResult>) {
-    let rules = [vowel_count(), has_repeated(), has_naughty_substring()];
-    let res = if validate_password(&password, &rules) {
-        ValidationResult::nice()
+#[post("/nice?<mode>", data = "<password>")]
+fn nice(
+    password: Json<Password>,
+    mode: Option<Mode>,
+    breaches: &State<Breaches>,
+) -> (Status, Json<ValidationResult>) {
+    let (status, result) = RuleSet::nice().validate(&password, mode.unwrap_or_default(), breaches);
+    (status, Json(result))
+}
+
+#[post("/game?<mode>", data = "<password>")]
+fn game(
+    password: Json<Password>,
+    mode: Option<Mode>,
+    breaches: &State<Breaches>,
+) -> (Status, Json<ValidationResult>) {
+    let (status, result) = RuleSet::game().validate(&password, mode.unwrap_or_default(), breaches);
+    (status, Json(result))
+}
+
+#[get("/rulesets")]
+fn list_rule_sets(rule_sets: &State<RuleSets>) -> Result<Json<Vec<String>>, Error> {
+    Ok(Json(rule_sets.names()?))
+}
+
+#[get("/rulesets/<name>")]
+fn get_rule_set(name: &str, rule_sets: &State<RuleSets>) -> Result<Json<RuleSet>, ApiError> {
+    rule_sets
+        .get(name)?
+        .map(Json)
+        .ok_or(ApiError::NotFound("Unknown rule set"))
+}
+
+#[put("/rulesets/<name>", data = "<set>")]
+fn put_rule_set(
+    _user: Authenticated,
+    name: &str,
+    set: Json<RuleSet>,
+    rule_sets: &State<RuleSets>,
+) -> Result<(Status, Json<RuleSet>), ApiError> {
+    let set = set.into_inner();
+    let status = if rule_sets.put(name, set.clone())? {
+        Status::Ok
     } else {
-        ValidationResult::naughty()
+        Status::Created
     };
-    ((&res).into(), Json(res))
+    Ok((status, Json(set)))
 }
 
-type Reason = Result<(), (Status, &'static str)>;
-type ValidatorWithReason = fn(&str) -> Reason;
+#[post("/rulesets/<name>/validate?<mode>", data = "<password>")]
+fn validate_with_rule_set(
+    name: &str,
+    password: Json<Password>,
+    mode: Option<Mode>,
+    rule_sets: &State<RuleSets>,
+    breaches: &State<Breaches>,
+) -> Result<(Status, Json<ValidationResult>), ApiError> {
+    let set = rule_sets
+        .get(name)?
+        .ok_or(ApiError::NotFound("Unknown rule set"))?;
+    let (status, result) = set.validate(&password, mode.unwrap_or_default(), breaches);
+    Ok((status, Json(result)))
+}
 
-fn validate_password_with_reason(password: &Password, rules: &[ValidatorWithReason]) -> Reason {
-    rules.iter().try_for_each(|r| r(&password.input))
+/// Searches for a password that passes a rule set, trying at most `budget` candidates
+#[get("/rulesets/<name>/generate?<budget>")]
+async fn generate_password(
+    name: &str,
+    budget: Option<u32>,
+    rule_sets: &State<RuleSets>,
+    breaches: &State<Breaches>,
+) -> Result<Json<generator::Generated>, ApiError> {
+    let set = rule_sets
+        .get(name)?
+        .ok_or(ApiError::NotFound("Unknown rule set"))?;
+    let budget = budget.unwrap_or(generator::DEFAULT_BUDGET);
+    let breaches = Breaches::clone(breaches);
+    // The search can take seconds, so keep it off the async workers
+    let generated = spawn_blocking(move || generator::generate(&set, budget, &breaches))
+        .await
+        .map_err(Error::from)??;
+    Ok(Json(generated))
 }
 
-fn eight_chars() -> ValidatorWithReason {
-    |input| {
-        if input.len() >= 8 {
-            Ok(())
-        } else {
-            Err((Status::BadRequest, "8 chars"))
-        }
-    }
+/// Whether a password is in the breached passwords corpus
+#[post("/breaches/check", data = "<password>")]
+fn check_breached(
+    password: Json<Password>,
+    breaches: &State<Breaches>,
+) -> Json<breaches::BreachReport> {
+    Json(breaches.report(&password.input.nfc().collect::<String>()))
 }
 
-fn upper_lower_digit() -> ValidatorWithReason {
-    |input| {
-        let (uppers, lowers, digits) =
-            input.chars().fold((false, false, false), |mut scores, ch| {
-                if ch.is_uppercase() {
-                    scores.0 = true;
-                }
-                if ch.is_lowercase() {
-                    scores.1 = true;
-                }
-                if ch.is_numeric() {
-                    scores.2 = true;
-                }
-                scores
-            });
-        if uppers && lowers && digits {
-            Ok(())
-        } else {
-            Err((Status::BadRequest, "more types of chars"))
-        }
-    }
+/// The breached hashes starting with a 5 hex digit prefix, so clients can check a password
+/// without sending it or its full hash
+#[get("/breaches/range/<prefix>")]
+fn breached_range(prefix: &str, breaches: &State<Breaches>) -> Result<String, InputError> {
+    breaches.range(prefix)
 }
 
-fn five_digits() -> ValidatorWithReason {
-    |input| {
-        let count = input.chars().filter(|c| c.is_numeric()).count();
+/// How guessable a password is, with what makes it so and how to improve it
+#[post("/strength", data = "<password>")]
+fn estimate_strength(password: Json<Password>) -> Json<strength::Estimate> {
+    Json(strength::estimate(
+        &password.input.nfc().collect::<String>(),
+    ))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        nice,
+        game,
+        estimate_strength,
+        list_rule_sets,
+        get_rule_set,
+        put_rule_set,
+        validate_with_rule_set,
+        generate_password,
+        check_breached,
+        breached_range
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rocket::fairing::AdHoc;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::{serde_json, Value};
+
+    use unicode_normalization::UnicodeNormalization;
+
+    use sqlx::SqlitePool;
+
+    use super::accounts::Accounts;
+    use super::{Breaches, Rule, RuleSets, Text};
 
-        if count >= 5 {
-            Ok(())
-        } else {
-            Err((Status::BadRequest, "55555"))
+    fn client() -> Client {
+        let breaches = Breaches::parse(include_str!("../wordlists/breached.txt")).unwrap();
+        Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .manage(RuleSets::new(HashMap::new()).unwrap())
+                .manage(breaches)
+                .attach(accounts()),
+        )
+        .unwrap()
+    }
+
+    struct TestToken(String);
+
+    /// Manages accounts with someone logged in, for the routes that need a session
+    fn accounts() -> AdHoc {
+        AdHoc::on_ignite("Test accounts", |rocket| async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let accounts = Accounts::new(pool).await.unwrap();
+            let token = accounts.test_session("elf").await;
+            rocket.manage(accounts).manage(TestToken(token))
+        })
+    }
+
+    fn auth(client: &Client) -> Header<'static> {
+        let TestToken(token) = client.rocket().state().unwrap();
+        Header::new("Authorization", format!("Bearer {token}"))
+    }
+
+    fn post(client: &Client, uri: &str, body: &str) -> (Status, Value) {
+        let response = client
+            .post(uri.to_string())
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        let status = response.status();
+        let body = serde_json::from_str(&response.into_string().unwrap()).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    #[test]
+    fn nice_test() {
+        let client = client();
+
+        let (status, body) = post(&client, "/nice", r#"{"input": "hello there"}"#);
+        assert_eq!(Status::Ok, status);
+        assert_eq!(serde_json::json!({"result": "nice"}), body);
+
+        let (status, body) = post(&client, "/nice", r#"{"input": "abcd"}"#);
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!(serde_json::json!({"result": "naughty"}), body);
+    }
+
+    #[test]
+    fn game_test() {
+        let client = client();
+
+        for (input, status, reason) in [
+            ("password", Status::BadRequest, "more types of chars"),
+            ("Password12345", Status::BadRequest, "math is hard"),
+            (
+                "23jPassword2000y",
+                Status::UnavailableForLegalReasons,
+                "illegal: no sandwich",
+            ),
+            (
+                "2000.23.AaA j o y",
+                Status::RangeNotSatisfiable,
+                "outranged",
+            ),
+            ("2000.23.AaA j o y ⦄", Status::UpgradeRequired, "😳"),
+            (
+                "2000.23.AaA j o y ⦄ 😀",
+                Status::ImATeapot,
+                "not a coffee brewer",
+            ),
+        ] {
+            let (actual_status, body) = post(
+                &client,
+                "/game",
+                &serde_json::json!({ "input": input }).to_string(),
+            );
+            assert_eq!(status, actual_status, "{input}");
+            assert_eq!(reason, body["reason"], "{input}");
         }
+
+        let (status, body) = post(
+            &client,
+            "/game",
+            r##"{"input": "2000.23.AaA j o y ⦄ 😀#*"}"##,
+        );
+        assert_eq!(Status::Ok, status, "{body}");
+        assert_eq!("that's a nice password", body["reason"]);
     }
-}
 
-fn math_is_hard() -> ValidatorWithReason {
-    |input| {
-        let groups = input.chars().fold(vec![String::new()], |mut groups, c| {
-            if c.is_numeric() {
-                groups
-                    .last_mut()
-                    .expect("Groups should never be empty")
-                    .push(c);
-            } else {
-                groups.push(String::new());
-            }
-            groups
+    #[test]
+    fn rule_sets_test() {
+        let client = client();
+
+        let set = r#"{
+            "rules": [
+                {"rule": "min_length", "min": 12, "reason": "too short"},
+                {"rule": "forbidden_substrings", "substrings": ["password"], "status": 422, "reason": "too obvious"}
+            ],
+            "success_reason": "welcome aboard"
+        }"#;
+        let response = client
+            .put("/rulesets/team")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(set)
+            .dispatch();
+        assert_eq!(Status::Created, response.status());
+
+        let (status, body) = post(&client, "/rulesets/team/validate", r#"{"input": "short"}"#);
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("too short", body["reason"]);
+        let (status, body) = post(
+            &client,
+            "/rulesets/team/validate",
+            r#"{"input": "my password is long"}"#,
+        );
+        assert_eq!(Status::UnprocessableEntity, status);
+        assert_eq!("too obvious", body["reason"]);
+        let (status, body) = post(
+            &client,
+            "/rulesets/team/validate",
+            r#"{"input": "correct horse battery"}"#,
+        );
+        assert_eq!(Status::Ok, status);
+        assert_eq!("welcome aboard", body["reason"]);
+
+        let response = client.get("/rulesets").dispatch();
+        assert_eq!(r#"["game","nice","team"]"#, response.into_string().unwrap());
+        let response = client.get("/rulesets/team").dispatch();
+        let stored: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(422, stored["rules"][1]["status"]);
+        assert_eq!(400, stored["rules"][0]["status"]);
+
+        // The built-ins behave like /15/nice and /15/game and can't be replaced
+        let (status, _) = post(&client, "/rulesets/nice/validate", r#"{"input": "abcd"}"#);
+        assert_eq!(Status::BadRequest, status);
+        let response = client
+            .put("/rulesets/game")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(set)
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+
+        for invalid in [
+            r#"{"rules": []}"#,
+            r#"{"rules": [{"rule": "min_length", "min": 1, "status": 200}]}"#,
+            r#"{"rules": [{"rule": "char_range", "start": "z", "end": "a"}]}"#,
+        ] {
+            let response = client
+                .put("/rulesets/broken")
+                .header(auth(&client))
+                .header(ContentType::JSON)
+                .body(invalid)
+                .dispatch();
+            assert_eq!(Status::BadRequest, response.status(), "{invalid}");
+        }
+        let (status, _) = post(&client, "/rulesets/broken/validate", r#"{"input": "x"}"#);
+        assert_eq!(Status::NotFound, status);
+
+        for name in ["NICE", "nice%20", "t%C3%A9am", "a.b"] {
+            let response = client
+                .put(format!("/rulesets/{name}"))
+                .header(auth(&client))
+                .header(ContentType::JSON)
+                .body(set)
+                .dispatch();
+            assert_eq!(Status::BadRequest, response.status(), "{name}");
+        }
+
+        let response = client
+            .put("/rulesets/anonymous")
+            .header(ContentType::JSON)
+            .body(set)
+            .dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+
+        let too_many_rules = serde_json::json!({
+            "rules": vec![serde_json::json!({"rule": "min_length", "min": 1}); super::MAX_RULES + 1]
         });
+        let response = client
+            .put("/rulesets/long")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(too_many_rules.to_string())
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
 
-        let sum: u32 = groups
-            .iter()
-            .filter(|s| !s.is_empty())
-            .filter_map(|s| s.parse::<u32>().ok())
-            .sum();
-
-        if sum == 2023 {
-            Ok(())
-        } else {
-            Err((Status::BadRequest, "math is hard"))
+        // Only the one runtime set so far, which can still be replaced once the rest are full
+        let put = |name: &str| {
+            client
+                .put(format!("/rulesets/{name}"))
+                .header(auth(&client))
+                .header(ContentType::JSON)
+                .body(set)
+                .dispatch()
+                .status()
+        };
+        for i in 1..super::MAX_RULE_SETS {
+            assert_eq!(Status::Created, put(&format!("team-{i}")));
         }
+        assert_eq!(Status::BadRequest, put("one-too-many"));
+        assert_eq!(Status::Ok, put("team"));
     }
-}
 
-fn joyful() -> ValidatorWithReason {
-    |input| {
-        let word = "joy";
+    #[test]
+    fn configured_rule_sets_test() {
+        let client = Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .attach(RuleSets::fairing())
+                .attach(Breaches::fairing())
+                .attach(accounts()),
+        )
+        .unwrap();
 
-        // find number of instances and check none are > 1
-        let one_of_each = word
-            .chars()
-            .map(|c| input.chars().filter(|cc| c == *cc).count())
-            .all(|n| n == 1);
-
-        // find index of each char and make sure they are in ascending order
-        let in_order = {
-            let indices: Vec<_> = word
-                .chars()
-                .map(|c| input.chars().position(|cc| c == cc))
-                .collect();
-            indices.windows(2).all(|pair| pair[0] < pair[1])
+        let (status, body) = post(
+            &client,
+            "/rulesets/staff/validate",
+            r#"{"input": "longbutlowercase"}"#,
+        );
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("mix upper case, lower case and digits", body["reason"]);
+
+        // The configured corpus is loaded for the staff set's `not_breached` rule
+        let (status, body) = post(
+            &client,
+            "/rulesets/staff/validate",
+            r#"{"input": "Correct4HorseBattery"}"#,
+        );
+        assert_eq!(Status::Ok, status, "{body}");
+        let (_, body) = post(&client, "/breaches/check", r#"{"input": "iloveyou"}"#);
+        assert_eq!(true, body["breached"]);
+
+        // Configured sets are as fixed as the built-in ones
+        let response = client
+            .put("/rulesets/staff")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(r#"{"rules": [{"rule": "min_length", "min": 1}]}"#)
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+    }
+
+    #[test]
+    fn all_failures_test() {
+        let client = client();
+
+        let (status, body) = post(&client, "/game?mode=all", r#"{"input": "password"}"#);
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("more types of chars", body["reason"]);
+        let failures: Vec<_> = body["failures"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| (f["rule"].as_str().unwrap(), f["status"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("character_classes", 400),
+                ("min_digits", 400),
+                ("digit_sum", 400),
+                ("ordered_once", 406),
+                ("sandwich", 451),
+                ("char_range", 416),
+                ("emoji", 426),
+                ("sha256_suffix", 418),
+            ],
+            failures
+        );
+        assert_eq!("not joyful enough", body["failures"][3]["reason"]);
+        assert_eq!(
+            serde_json::json!([{"index": 0, "rule": "min_length", "min": 8}]),
+            body["passed"]
+        );
+
+        let (status, body) = post(
+            &client,
+            "/game?mode=all",
+            r##"{"input": "2000.23.AaA j o y ⦄ 😀#*"}"##,
+        );
+        assert_eq!(Status::Ok, status);
+        assert_eq!(serde_json::json!([]), body["failures"]);
+        assert_eq!(9, body["passed"].as_array().unwrap().len());
+
+        // The default is still to stop at the first failure
+        let (_, body) = post(&client, "/game?mode=first", r#"{"input": "password"}"#);
+        assert_eq!(
+            serde_json::json!({"result": "naughty", "reason": "more types of chars"}),
+            body
+        );
+        let (status, body) = post(
+            &client,
+            "/rulesets/nice/validate?mode=all",
+            r#"{"input": "abcd"}"#,
+        );
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!(3, body["failures"].as_array().unwrap().len());
+    }
+
+    fn passes(rule: Rule, input: &str) -> bool {
+        let nfc: String = input.nfc().collect();
+        rule.passes(&Text::new(&nfc), &Breaches::default())
+    }
+
+    #[test]
+    fn grapheme_length_test() {
+        let min = |min| Rule::MinLength { min };
+
+        // Seven decomposed "é"s are 14 code points and 21 bytes, but seven characters
+        assert!(!passes(min(8), &"e\u{301}".repeat(7)));
+        assert!(passes(min(7), &"e\u{301}".repeat(7)));
+        // A family is one character however many people are in it
+        assert!(!passes(min(2), "👨‍👩‍👧‍👦"));
+        assert!(passes(min(8), &format!("{}a", "👍🏽".repeat(7))));
+        // Right-to-left text counts the same as any other
+        assert!(passes(min(5), "مرحبا"));
+        assert!(!passes(min(6), "مرحبا"));
+        assert!(passes(min(4), "שָׁלוֹם"));
+        assert!(!passes(min(5), "שָׁלוֹם"));
+    }
+
+    #[test]
+    fn combining_marks_test() {
+        let forbidden = |substrings: &[&str]| Rule::ForbiddenSubstrings {
+            substrings: substrings.iter().map(ToString::to_string).collect(),
         };
 
-        if one_of_each && in_order {
-            Ok(())
-        } else {
-            Err((Status::NotAcceptable, "not joyful enough"))
-        }
+        // Composed and decomposed forms are the same password after NFC
+        assert!(!passes(forbidden(&["café"]), "my cafe\u{301} password"));
+        assert!(!passes(forbidden(&["cafe\u{301}"]), "my café password"));
+        // "a" followed by a combining accent isn't the letter "a"
+        assert!(passes(forbidden(&["ab"]), "xa\u{301}by"));
+        assert!(!passes(forbidden(&["ab"]), "日本ab"));
+
+        assert!(passes(Rule::RepeatedLetter, "n\u{303}ñ"));
+        assert!(!passes(Rule::RepeatedLetter, "e\u{301}e"));
+        assert!(!passes(Rule::RepeatedLetter, "😀😀"));
+
+        assert!(passes(Rule::Sandwich { min: 1 }, "e\u{301}xé"));
+        assert!(!passes(Rule::Sandwich { min: 1 }, "e\u{301}xe"));
+        assert!(!passes(Rule::Sandwich { min: 1 }, "😀x😀"));
+
+        let joy = || Rule::OrderedOnce { word: "joy".into() };
+        assert!(passes(joy(), "j\u{301}-joy"));
+        assert!(!passes(joy(), "j\u{301}oy"));
+
+        let vowels = || Rule::MinVowels {
+            min: 3,
+            vowels: "aeiouyé".into(),
+        };
+        assert!(passes(vowels(), "e\u{301}te\u{301}e"));
+        assert!(!passes(vowels(), "xa\u{308}o\u{308}u\u{308}"));
     }
-}
 
-fn sandwich() -> ValidatorWithReason {
-    |input| {
-        let sandwiches = input
-            .bytes()
-            .enumerate()
-            .filter(|(i, c)| {
-                c.is_ascii_alphabetic()
-                    && matches!(
-                    (input.as_bytes().get(i + 1), input.as_bytes().get(i + 2)),
-                    (Some(d), Some(e)) if d != c && e == c)
-            })
-            .count();
-
-        if sandwiches >= 1 {
-            Ok(())
-        } else {
-            Err((Status::UnavailableForLegalReasons, "illegal: no sandwich"))
+    #[test]
+    fn emoji_and_digits_test() {
+        assert!(passes(Rule::Emoji, "☺\u{fe0f}"));
+        assert!(!passes(Rule::Emoji, "☺"));
+        assert!(passes(Rule::Emoji, "👍🏽"));
+        assert!(passes(Rule::Emoji, "pass👨‍👩‍👧‍👦word"));
+
+        // A keycap is an emoji, not a digit
+        assert!(passes(Rule::Emoji, "1\u{fe0f}\u{20e3}"));
+        assert!(!passes(Rule::MinDigits { min: 1 }, "1\u{fe0f}\u{20e3}"));
+        assert!(!passes(
+            Rule::DigitSum { sum: 2023 },
+            "2000 2\u{fe0f}\u{20e3}3"
+        ));
+        assert!(passes(Rule::DigitSum { sum: 2023 }, "2000 twenty 23"));
+        // Only ASCII digits count, the same ones the digit sum adds up
+        for other in ["كلمة١٢٣٤٥", "½", "Ⅻ"] {
+            assert!(!passes(Rule::MinDigits { min: 1 }, other), "{other}");
+            assert!(!passes(
+                Rule::CharacterClasses {
+                    upper: false,
+                    lower: false,
+                    digit: true
+                },
+                other
+            ));
         }
+        assert!(passes(Rule::DigitSum { sum: 2023 }, "2000١٢23"));
+
+        // Sums past u32 don't wrap around onto the target
+        assert!(!passes(
+            Rule::DigitSum { sum: 2023 },
+            "4000000000x294969319"
+        ));
+        assert!(!passes(
+            Rule::DigitSum { sum: 2023 },
+            "99999999999999999999999 2023"
+        ));
+        assert!(passes(
+            Rule::DigitSum { sum: 2023 },
+            "0000000000000000000002023"
+        ));
     }
-}
 
-fn math_unicode() -> ValidatorWithReason {
-    |input| {
-        let passes = input
-            .chars()
-            .any(|c| ('\u{2980}'..='\u{2BFF}').contains(&c));
+    #[test]
+    fn multibyte_passwords_test() {
+        let client = client();
 
-        if passes {
-            Ok(())
-        } else {
-            Err((Status::RangeNotSatisfiable, "outranged"))
-        }
+        // These used to panic on slicing through a multibyte character
+        let (status, _) = post(&client, "/nice", r#"{"input": "日本ab"}"#);
+        assert_eq!(Status::BadRequest, status);
+        let (status, body) = post(&client, "/nice", r#"{"input": "aéiouüxx"}"#);
+        assert_eq!(Status::Ok, status, "{body}");
+        let (status, body) = post(&client, "/game?mode=all", r#"{"input": "é日本語😀"}"#);
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("8 chars", body["reason"]);
     }
-}
 
-fn has_emojis() -> ValidatorWithReason {
-    |input| {
-        let passes = input.chars().any(unic_emoji_char::is_emoji_presentation);
+    #[test]
+    fn strength_test() {
+        let client = client();
 
-        if passes {
-            Ok(())
-        } else {
-            Err((Status::UpgradeRequired, "😳"))
-        }
+        let (status, body) = post(&client, "/strength", r#"{"input": "Password1"}"#);
+        assert_eq!(Status::Ok, status);
+        assert_eq!(0, body["score"]);
+        assert_eq!("dictionary", body["patterns"][0]["pattern"]);
+        assert_eq!("password1", body["patterns"][0]["word"]);
+        assert!(body["crack_time"]["online_throttled"]["display"].is_string());
+        assert!(!body["suggestions"].as_array().unwrap().is_empty());
+
+        let set = r#"{"rules": [{"rule": "min_strength", "score": 3, "reason": "too guessable"}]}"#;
+        let (status, _) = post(&client, "/rulesets/strong/validate", r#"{"input": "x"}"#);
+        assert_eq!(Status::NotFound, status);
+        let response = client
+            .put("/rulesets/strong")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(set)
+            .dispatch();
+        assert_eq!(Status::Created, response.status());
+        let (status, body) = post(
+            &client,
+            "/rulesets/strong/validate",
+            r#"{"input": "qwerty123"}"#,
+        );
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("too guessable", body["reason"]);
+        let (status, _) = post(
+            &client,
+            "/rulesets/strong/validate",
+            r#"{"input": "correct horse battery staple"}"#,
+        );
+        assert_eq!(Status::Ok, status);
     }
-}
 
-fn correct_hash() -> ValidatorWithReason {
-    |input| {
-        let digest = HEXLOWER.encode(digest::digest(&digest::SHA256, input.as_bytes()).as_ref());
+    #[test]
+    fn generate_test() {
+        let client = client();
 
-        if digest.bytes().last() == Some(b'a') {
-            Ok(())
-        } else {
-            Err((Status::ImATeapot, "not a coffee brewer"))
+        for name in ["nice", "game", "strong"] {
+            if name == "strong" {
+                let set = r#"{"rules": [
+                    {"rule": "min_length", "min": 20},
+                    {"rule": "forbidden_substrings", "substrings": ["aa", "b"]},
+                    {"rule": "min_vowels", "min": 2, "vowels": "ab"},
+                    {"rule": "min_strength", "score": 4}
+                ]}"#;
+                let response = client
+                    .put("/rulesets/strong")
+                    .header(auth(&client))
+                    .header(ContentType::JSON)
+                    .body(set)
+                    .dispatch();
+                assert_eq!(Status::Created, response.status());
+            }
+
+            let response = client.get(format!("/rulesets/{name}/generate")).dispatch();
+            assert_eq!(Status::Ok, response.status(), "{name}");
+            let body: Value = response.into_json().unwrap();
+            let password = body["password"].as_str().unwrap();
+            assert!(body["attempts"].as_u64().unwrap() >= 1);
+
+            let (status, body) = post(
+                &client,
+                &format!("/rulesets/{name}/validate?mode=all"),
+                &serde_json::json!({ "input": password }).to_string(),
+            );
+            assert_eq!(Status::Ok, status, "{name}: {password} {body}");
         }
-    }
-}
 
-#[post("/game", data = "<password>")]
-fn game(password: Json<Password>) -> (Status, Json<ValidationResult>) {
-    let rules = [
-        eight_chars(),
-        upper_lower_digit(),
-        five_digits(),
-        math_is_hard(),
-        joyful(),
-        sandwich(),
-        math_unicode(),
-        has_emojis(),
-        correct_hash(),
-    ];
-    if let Err((status, reason)) = validate_password_with_reason(&password, &rules) {
-        (status, Json(ValidationResult::naughty_with_reason(reason)))
-    } else {
-        (Status::Ok, Json(ValidationResult::nice_with_reason()))
+        // Each grapheme of "aa" has to appear only once
+        let response = client
+            .put("/rulesets/impossible")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(r#"{"rules": [{"rule": "ordered_once", "word": "aa"}]}"#)
+            .dispatch();
+        assert_eq!(Status::Created, response.status());
+        let response = client.get("/rulesets/impossible/generate").dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        assert_eq!(
+            "No password passing every rule was found within the search budget",
+            response.into_string().unwrap()
+        );
+
+        // The first candidate always has what a length rule asks for, and never what the
+        // impossible set does
+        let response = client
+            .put("/rulesets/long")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(r#"{"rules": [{"rule": "min_length", "min": 30}]}"#)
+            .dispatch();
+        assert_eq!(Status::Created, response.status());
+        let response = client.get("/rulesets/long/generate?budget=1").dispatch();
+        assert_eq!(Status::Ok, response.status());
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(1, body["attempts"]);
+        let response = client
+            .get("/rulesets/impossible/generate?budget=1")
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        let response = client
+            .get("/rulesets/game/generate?budget=2000000")
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        let response = client.get("/rulesets/missing/generate").dispatch();
+        assert_eq!(Status::NotFound, response.status());
     }
-}
 
-pub fn routes() -> Vec<Route> {
-    routes![nice, game]
+    #[test]
+    fn breaches_test() {
+        let client = client();
+
+        let (status, body) = post(&client, "/breaches/check", r#"{"input": "password"}"#);
+        assert_eq!(Status::Ok, status);
+        assert_eq!(serde_json::json!({"breached": true, "count": 1}), body);
+        let (_, body) = post(
+            &client,
+            "/breaches/check",
+            r#"{"input": "2000.23.AaA j o y"}"#,
+        );
+        assert_eq!(serde_json::json!({"breached": false, "count": 0}), body);
+
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let response = client.get("/breaches/range/5baa6").dispatch();
+        assert_eq!(Status::Ok, response.status());
+        let range = response.into_string().unwrap();
+        assert!(
+            range
+                .lines()
+                .any(|line| line == "1E4C9B93F3F0682250B6CF8331B7EE68FD8:1"),
+            "{range}"
+        );
+        let response = client.get("/breaches/range/5baa").dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+
+        let set = r#"{"rules": [{"rule": "not_breached", "reason": "breached"}]}"#;
+        let response = client
+            .put("/rulesets/unbreached")
+            .header(auth(&client))
+            .header(ContentType::JSON)
+            .body(set)
+            .dispatch();
+        assert_eq!(Status::Created, response.status());
+        let (status, body) = post(
+            &client,
+            "/rulesets/unbreached/validate",
+            r#"{"input": "qwerty"}"#,
+        );
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("breached", body["reason"]);
+        let (status, _) = post(
+            &client,
+            "/rulesets/unbreached/validate",
+            r#"{"input": "qwertz"}"#,
+        );
+        assert_eq!(Status::Ok, status);
+    }
 }
@@ -0,0 +1,658 @@
+//! Accounts whose passwords have to pass a rule set, with session tokens so other routes can
+//! require a logged in user
+
+use std::num::NonZeroU32;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::task::spawn_blocking;
+use rocket::{delete, get, post, put, routes, Request, Responder, Route, State};
+use sqlx::{Executor, FromRow, SqlitePool};
+use unicode_normalization::UnicodeNormalization;
+
+use super::{Breaches, Mode, Password, RuleSets, ValidationResult};
+use crate::common::{config_fairing, ApiError, Error, InputError};
+
+static ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const HASH_LENGTH: usize = digest::SHA256_OUTPUT_LEN;
+const SALT_LENGTH: usize = 16;
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AccountsConfig {
+    /// The rule set passwords have to pass
+    rule_set: String,
+    /// PBKDF2 iterations for new hashes, existing ones keep the count they were made with
+    iterations: NonZeroU32,
+    session_hours: i64,
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        Self {
+            rule_set: "nice".into(),
+            iterations: NonZeroU32::new(600_000).unwrap(),
+            session_hours: 24,
+        }
+    }
+}
+
+impl AccountsConfig {
+    pub fn fairing() -> AdHoc {
+        config_fairing::<Self>("Accounts", "accounts")
+    }
+}
+
+#[derive(FromRow)]
+struct StoredUser {
+    id: i64,
+    username: String,
+    salt: Vec<u8>,
+    password_hash: Vec<u8>,
+    iterations: u32,
+}
+
+impl StoredUser {
+    async fn verify(&self, password: &str) -> Result<bool, Error> {
+        let Some(iterations) = NonZeroU32::new(self.iterations) else {
+            return Ok(false);
+        };
+        let password = normalized(password);
+        let salt = self.salt.clone();
+        let password_hash = self.password_hash.clone();
+        let verified = spawn_blocking(move || {
+            pbkdf2::verify(
+                ALGORITHM,
+                iterations,
+                &salt,
+                password.as_bytes(),
+                &password_hash,
+            )
+            .is_ok()
+        })
+        .await?;
+        Ok(verified)
+    }
+}
+
+/// Passwords are hashed in NFC, the form the rule sets check them in, so the same password
+/// typed on a keyboard that composes characters differently still matches
+fn normalized(password: &str) -> String {
+    password.nfc().collect()
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Account {
+    id: i64,
+    username: String,
+}
+
+/// Users and their sessions, stored in SQLite
+pub struct Accounts {
+    pool: SqlitePool,
+    random: SystemRandom,
+}
+
+impl Accounts {
+    pub async fn new(pool: SqlitePool) -> Result<Self, Error> {
+        pool.execute(include_str!("../../db/schema_15.sql")).await?;
+        Ok(Self {
+            pool,
+            random: SystemRandom::new(),
+        })
+    }
+
+    fn random_bytes<const N: usize>(&self) -> Result<[u8; N], Error> {
+        let mut bytes = [0; N];
+        self.random.fill(&mut bytes).map_err(|_| Error {
+            message: "Couldn't generate random bytes",
+        })?;
+        Ok(bytes)
+    }
+
+    /// PBKDF2 is slow on purpose, so it runs on a blocking thread rather than an async worker
+    async fn hash(
+        password: &str,
+        salt: [u8; SALT_LENGTH],
+        iterations: NonZeroU32,
+    ) -> Result<[u8; HASH_LENGTH], Error> {
+        let password = normalized(password);
+        let hash = spawn_blocking(move || {
+            let mut hash = [0; HASH_LENGTH];
+            pbkdf2::derive(ALGORITHM, iterations, &salt, password.as_bytes(), &mut hash);
+            hash
+        })
+        .await?;
+        Ok(hash)
+    }
+
+    /// Adds a user, or returns `None` if the username is taken
+    async fn create(
+        &self,
+        username: &str,
+        password: &str,
+        iterations: NonZeroU32,
+    ) -> Result<Option<Account>, Error> {
+        let salt: [u8; SALT_LENGTH] = self.random_bytes()?;
+        let hash = Self::hash(password, salt, iterations).await?;
+        let result = sqlx::query(
+            "INSERT INTO users (username, salt, password_hash, iterations, created_at)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(username)
+        .bind(salt.as_slice())
+        .bind(hash.as_slice())
+        .bind(iterations.get())
+        .bind(Utc::now().timestamp())
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(result) => Ok(Some(Account {
+                id: result.last_insert_rowid(),
+                username: username.into(),
+            })),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The user with these credentials, if they're right
+    async fn verify(
+        &self,
+        username: &str,
+        password: &str,
+        iterations: NonZeroU32,
+    ) -> Result<Option<Account>, Error> {
+        let user: Option<StoredUser> = sqlx::query_as(
+            "SELECT id, username, salt, password_hash, iterations FROM users WHERE username = $1",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(user) = user else {
+            // Take as long as a wrong password would, so response times don't give away which
+            // usernames exist
+            Self::hash(password, [0; SALT_LENGTH], iterations).await?;
+            return Ok(None);
+        };
+        Ok(user.verify(password).await?.then_some(Account {
+            id: user.id,
+            username: user.username,
+        }))
+    }
+
+    async fn set_password(
+        &self,
+        user_id: i64,
+        password: &str,
+        iterations: NonZeroU32,
+    ) -> Result<(), Error> {
+        let salt: [u8; SALT_LENGTH] = self.random_bytes()?;
+        let hash = Self::hash(password, salt, iterations).await?;
+        sqlx::query(
+            "UPDATE users SET salt = $2, password_hash = $3, iterations = $4 WHERE id = $1",
+        )
+        .bind(user_id)
+        .bind(salt.as_slice())
+        .bind(hash.as_slice())
+        .bind(iterations.get())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Only a hash of each token is stored, so a leaked database doesn't leak live sessions
+    fn token_hash(token: &str) -> Vec<u8> {
+        digest::digest(&digest::SHA256, token.as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+
+    async fn start_session(&self, user_id: i64, hours: i64) -> Result<Session, Error> {
+        let token = URL_SAFE_NO_PAD.encode(self.random_bytes::<TOKEN_LENGTH>()?);
+        let expires_at = Utc::now().timestamp() + hours * 60 * 60;
+        sqlx::query("INSERT INTO sessions (token_hash, user_id, expires_at) VALUES ($1, $2, $3)")
+            .bind(Self::token_hash(&token))
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(Session { token, expires_at })
+    }
+
+    async fn session(&self, token: &str) -> Result<Option<Authenticated>, Error> {
+        let token_hash = Self::token_hash(token);
+        let user: Option<(i64, String)> = sqlx::query_as(
+            "SELECT u.id, u.username FROM sessions s
+            JOIN users u ON u.id = s.user_id
+            WHERE s.token_hash = $1 AND s.expires_at > $2",
+        )
+        .bind(&token_hash)
+        .bind(Utc::now().timestamp())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user.map(|(user_id, username)| Authenticated {
+            user_id,
+            username,
+            token_hash,
+        }))
+    }
+
+    /// Signs up `username` and logs them in, for testing routes that need a session
+    #[cfg(test)]
+    pub async fn test_session(&self, username: &str) -> String {
+        let account = self
+            .create(username, "correct horse battery staple", NonZeroU32::MIN)
+            .await
+            .unwrap()
+            .expect("username should be free");
+        self.start_session(account.id, 1).await.unwrap().token
+    }
+
+    /// Ends a user's sessions, apart from `keep`
+    async fn end_other_sessions(&self, user_id: i64, keep: &[u8]) -> Result<(), Error> {
+        sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND token_hash != $2")
+            .bind(user_id)
+            .bind(keep)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// A request with a valid `Authorization: Bearer <token>` header from logging in
+pub struct Authenticated {
+    pub user_id: i64,
+    pub username: String,
+    token_hash: Vec<u8>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authenticated {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(accounts) = req.rocket().state::<Accounts>() else {
+            return request::Outcome::Error((Status::InternalServerError, "No accounts"));
+        };
+        let Some(token) = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        else {
+            return request::Outcome::Error((Status::Unauthorized, "Missing session token"));
+        };
+        match accounts.session(token).await {
+            Ok(Some(authenticated)) => request::Outcome::Success(authenticated),
+            Ok(None) => request::Outcome::Error((Status::Unauthorized, "Invalid session token")),
+            Err(e) => request::Outcome::Error((Status::InternalServerError, e.message)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Session {
+    token: String,
+    expires_at: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PasswordChange {
+    current: String,
+    new: String,
+}
+
+/// Either what the handler made of an acceptable password, or why the password was refused
+#[derive(Responder)]
+enum Checked<T> {
+    Accepted(T),
+    Rejected((Status, Json<ValidationResult>)),
+}
+
+/// Runs a password through the configured rule set, reporting every rule it breaks
+fn check_password(
+    password: &str,
+    config: &AccountsConfig,
+    rule_sets: &RuleSets,
+    breaches: &Breaches,
+) -> Result<Option<(Status, Json<ValidationResult>)>, Error> {
+    let set = rule_sets.get(&config.rule_set)?.ok_or(Error {
+        message: "The accounts rule set doesn't exist",
+    })?;
+    let password = Password {
+        input: password.into(),
+    };
+    let (status, result) = set.validate(&password, Mode::All, breaches);
+    Ok((status != Status::Ok).then_some((status, Json(result))))
+}
+
+fn check_username(username: &str) -> Result<(), InputError> {
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+    if !(3..=32).contains(&username.len()) || !valid_chars {
+        return Err(InputError {
+            message: "Usernames are 3 to 32 letters, digits, '_', '-' or '.'",
+        });
+    }
+    Ok(())
+}
+
+#[post("/register", data = "<credentials>")]
+async fn register(
+    credentials: Json<Credentials>,
+    accounts: &State<Accounts>,
+    config: &State<AccountsConfig>,
+    rule_sets: &State<RuleSets>,
+    breaches: &State<Breaches>,
+) -> Result<Checked<(Status, Json<Account>)>, ApiError> {
+    check_username(&credentials.username)?;
+    if let Some(rejected) = check_password(&credentials.password, config, rule_sets, breaches)? {
+        return Ok(Checked::Rejected(rejected));
+    }
+    let account = accounts
+        .create(
+            &credentials.username,
+            &credentials.password,
+            config.iterations,
+        )
+        .await?
+        .ok_or(ApiError::Conflict("Username is taken"))?;
+    Ok(Checked::Accepted((Status::Created, Json(account))))
+}
+
+#[post("/login", data = "<credentials>")]
+async fn login(
+    credentials: Json<Credentials>,
+    accounts: &State<Accounts>,
+    config: &State<AccountsConfig>,
+) -> Result<Json<Session>, ApiError> {
+    let account = accounts
+        .verify(
+            &credentials.username,
+            &credentials.password,
+            config.iterations,
+        )
+        .await?
+        .ok_or(ApiError::Unauthorized("Wrong username or password"))?;
+    Ok(Json(
+        accounts
+            .start_session(account.id, config.session_hours)
+            .await?,
+    ))
+}
+
+#[get("/me")]
+fn me(user: Authenticated) -> Json<Account> {
+    Json(Account {
+        id: user.user_id,
+        username: user.username,
+    })
+}
+
+/// Changes the password, logging out every other session
+#[put("/password", data = "<change>")]
+async fn change_password(
+    user: Authenticated,
+    change: Json<PasswordChange>,
+    accounts: &State<Accounts>,
+    config: &State<AccountsConfig>,
+    rule_sets: &State<RuleSets>,
+    breaches: &State<Breaches>,
+) -> Result<Checked<Status>, ApiError> {
+    accounts
+        .verify(&user.username, &change.current, config.iterations)
+        .await?
+        .ok_or(ApiError::Unauthorized("Wrong password"))?;
+    if let Some(rejected) = check_password(&change.new, config, rule_sets, breaches)? {
+        return Ok(Checked::Rejected(rejected));
+    }
+    accounts
+        .set_password(user.user_id, &change.new, config.iterations)
+        .await?;
+    accounts
+        .end_other_sessions(user.user_id, &user.token_hash)
+        .await?;
+    Ok(Checked::Accepted(Status::NoContent))
+}
+
+#[delete("/session")]
+async fn logout(user: Authenticated, accounts: &State<Accounts>) -> Result<Status, Error> {
+    sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+        .bind(&user.token_hash)
+        .execute(&accounts.pool)
+        .await?;
+    Ok(Status::NoContent)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![register, login, me, change_password, logout]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
+
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::{serde_json, Value};
+    use sqlx::SqlitePool;
+
+    use super::{Accounts, AccountsConfig};
+    use crate::day_15::{Breaches, RuleSets};
+
+    async fn client() -> Client {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let rules = r#"{"rules": [
+            {"rule": "min_length", "min": 10, "reason": "too short"},
+            {"rule": "not_breached", "reason": "breached"}
+        ]}"#;
+        let rule_sets = RuleSets::new(HashMap::from([(
+            "accounts".into(),
+            serde_json::from_str(rules).unwrap(),
+        )]))
+        .unwrap();
+        let config = AccountsConfig {
+            rule_set: "accounts".into(),
+            iterations: NonZeroU32::new(1_000).unwrap(),
+            ..AccountsConfig::default()
+        };
+        Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .manage(Accounts::new(pool).await.unwrap())
+                .manage(config)
+                .manage(rule_sets)
+                .manage(Breaches::parse(include_str!("../../wordlists/breached.txt")).unwrap()),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn send(
+        client: &Client,
+        method: &str,
+        uri: &'static str,
+        token: Option<&str>,
+        body: Value,
+    ) -> (Status, Value) {
+        let request = match method {
+            "POST" => client.post(uri),
+            "PUT" => client.put(uri),
+            "DELETE" => client.delete(uri),
+            _ => client.get(uri),
+        };
+        let request = match token {
+            Some(token) => request.header(Header::new("Authorization", format!("Bearer {token}"))),
+            None => request,
+        };
+        let response = request
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await;
+        let status = response.status();
+        let body = response.into_json().await.unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    async fn login(client: &Client, password: &str) -> (Status, String) {
+        let credentials = serde_json::json!({"username": "elf", "password": password});
+        let (status, body) = send(client, "POST", "/login", None, credentials).await;
+        (status, body["token"].as_str().unwrap_or_default().into())
+    }
+
+    #[rocket::async_test]
+    async fn register_test() {
+        let client = client().await;
+        let register = |username: &str, password: &str| serde_json::json!({"username": username, "password": password});
+
+        let (status, body) = send(
+            &client,
+            "POST",
+            "/register",
+            None,
+            register("elf", "password"),
+        )
+        .await;
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("too short", body["reason"]);
+        assert_eq!(2, body["failures"].as_array().unwrap().len());
+
+        let (status, body) = send(
+            &client,
+            "POST",
+            "/register",
+            None,
+            register("elf", "sugar plum fairy"),
+        )
+        .await;
+        assert_eq!(Status::Created, status);
+        assert_eq!("elf", body["username"]);
+
+        let (status, _) = send(
+            &client,
+            "POST",
+            "/register",
+            None,
+            register("ELF", "another long one"),
+        )
+        .await;
+        assert_eq!(Status::Conflict, status);
+        let (status, _) = send(
+            &client,
+            "POST",
+            "/register",
+            None,
+            register("e", "another long one"),
+        )
+        .await;
+        assert_eq!(Status::BadRequest, status);
+    }
+
+    #[rocket::async_test]
+    async fn sessions_test() {
+        let client = client().await;
+        let credentials = serde_json::json!({"username": "elf", "password": "sugar plum fairy"});
+        send(&client, "POST", "/register", None, credentials).await;
+
+        assert_eq!(
+            Status::Unauthorized,
+            login(&client, "sugar plum fair").await.0
+        );
+        let unknown = serde_json::json!({"username": "santa", "password": "sugar plum fairy"});
+        let (status, _) = send(&client, "POST", "/login", None, unknown).await;
+        assert_eq!(Status::Unauthorized, status);
+
+        let (status, token) = login(&client, "sugar plum fairy").await;
+        assert_eq!(Status::Ok, status);
+        let (status, body) = send(&client, "GET", "/me", Some(&token), Value::Null).await;
+        assert_eq!(Status::Ok, status);
+        assert_eq!("elf", body["username"]);
+        let (status, _) = send(&client, "GET", "/me", None, Value::Null).await;
+        assert_eq!(Status::Unauthorized, status);
+        let (status, _) = send(&client, "GET", "/me", Some("made-up"), Value::Null).await;
+        assert_eq!(Status::Unauthorized, status);
+
+        let (_, other) = login(&client, "sugar plum fairy").await;
+        let change = |current: &str, new: &str| serde_json::json!({"current": current, "new": new});
+        let (status, _) = send(
+            &client,
+            "PUT",
+            "/password",
+            Some(&token),
+            change("wrong", "nutcracker suite"),
+        )
+        .await;
+        assert_eq!(Status::Unauthorized, status);
+        let (status, body) = send(
+            &client,
+            "PUT",
+            "/password",
+            Some(&token),
+            change("sugar plum fairy", "iloveyou"),
+        )
+        .await;
+        assert_eq!(Status::BadRequest, status);
+        assert_eq!("too short", body["reason"]);
+        let (status, _) = send(
+            &client,
+            "PUT",
+            "/password",
+            Some(&token),
+            change("sugar plum fairy", "nutcracker suite"),
+        )
+        .await;
+        assert_eq!(Status::NoContent, status);
+
+        // Changing the password logs out everywhere else
+        let (status, _) = send(&client, "GET", "/me", Some(&other), Value::Null).await;
+        assert_eq!(Status::Unauthorized, status);
+        let (status, _) = send(&client, "GET", "/me", Some(&token), Value::Null).await;
+        assert_eq!(Status::Ok, status);
+        assert_eq!(
+            Status::Unauthorized,
+            login(&client, "sugar plum fairy").await.0
+        );
+        assert_eq!(Status::Ok, login(&client, "nutcracker suite").await.0);
+
+        let (status, _) = send(&client, "DELETE", "/session", Some(&token), Value::Null).await;
+        assert_eq!(Status::NoContent, status);
+        let (status, _) = send(&client, "GET", "/me", Some(&token), Value::Null).await;
+        assert_eq!(Status::Unauthorized, status);
+    }
+
+    #[rocket::async_test]
+    async fn normalized_password_test() {
+        let client = client().await;
+        // Registered with decomposed accents, logging in with composed ones
+        let credentials =
+            serde_json::json!({"username": "elf", "password": "cre\u{300}me bru\u{302}le\u{301}e"});
+        let (status, _) = send(&client, "POST", "/register", None, credentials).await;
+        assert_eq!(Status::Created, status);
+
+        assert_eq!(Status::Ok, login(&client, "crème brûlée").await.0);
+        assert_eq!(Status::Unauthorized, login(&client, "creme brulee").await.0);
+    }
+}
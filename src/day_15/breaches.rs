@@ -0,0 +1,178 @@
+//! Passwords known from breaches, as SHA-1 hashes in the format of the Pwned Passwords downloads,
+//! so they can be checked locally or looked up by hash prefix without sending the password
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use data_encoding::HEXUPPER;
+use ring::digest;
+use rocket::fairing::AdHoc;
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::common::{read_config, InputError};
+
+/// Hex digits of a hash prefix in a range query
+pub const PREFIX_LENGTH: usize = 5;
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct BreachesConfig {
+    /// File of `HASH[:COUNT]` lines, no corpus means nothing counts as breached
+    corpus: Option<PathBuf>,
+}
+
+type Hash = [u8; 20];
+
+/// Breached password hashes, sorted so both lookups and range queries are binary searches.
+/// Clones share the hashes, so blocking tasks can take the corpus along cheaply.
+#[derive(Default, Clone)]
+pub struct Breaches {
+    hashes: Arc<[(Hash, u32)]>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BreachReport {
+    breached: bool,
+    /// How many times the password has been seen in breaches
+    count: u32,
+}
+
+impl Breaches {
+    pub fn parse(corpus: &str) -> Result<Self, InputError> {
+        let mut hashes = corpus
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (hash, count) = line.split_once(':').unwrap_or((line, "1"));
+                let hash: Hash = HEXUPPER
+                    .decode(hash.to_ascii_uppercase().as_bytes())
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or(InputError {
+                        message: "Breach corpus lines should be a hex SHA-1, optionally followed \
+                            by `:COUNT`",
+                    })?;
+                Ok((hash, count.parse::<u32>()?))
+            })
+            .collect::<Result<Vec<_>, InputError>>()?;
+        hashes.sort_unstable();
+        // A hash listed more than once, say from merged dumps, has been seen that many more times
+        hashes.dedup_by(|(hash, count), (kept_hash, kept_count)| {
+            let duplicate = hash == kept_hash;
+            if duplicate {
+                *kept_count = kept_count.saturating_add(*count);
+            }
+            duplicate
+        });
+        Ok(Self {
+            hashes: hashes.into(),
+        })
+    }
+
+    /// Reads the `breaches` config and manages the corpus it points to
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Breached passwords", |rocket| async move {
+            let Some(config) = read_config::<BreachesConfig>(rocket.figment(), "breaches") else {
+                return Err(rocket);
+            };
+            let Some(path) = config.corpus else {
+                return Ok(rocket.manage(Self::default()));
+            };
+            let corpus = match rocket::tokio::fs::read_to_string(&path).await {
+                Ok(corpus) => corpus,
+                Err(e) => {
+                    rocket::error!("Couldn't read breach corpus {}: {e}", path.display());
+                    return Err(rocket);
+                }
+            };
+            match Self::parse(&corpus) {
+                Ok(breaches) => {
+                    rocket::info!("Loaded {} breached password hashes", breaches.hashes.len());
+                    Ok(rocket.manage(breaches))
+                }
+                Err(e) => {
+                    rocket::error!("Invalid breach corpus {}: {e}", path.display());
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    /// How many times a password has been seen, 0 if never
+    pub fn count(&self, password: &str) -> u32 {
+        let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+        self.hashes
+            .binary_search_by(|(h, _)| h.as_slice().cmp(hash.as_ref()))
+            .map_or(0, |i| self.hashes[i].1)
+    }
+
+    pub fn report(&self, password: &str) -> BreachReport {
+        let count = self.count(password);
+        BreachReport {
+            breached: count > 0,
+            count,
+        }
+    }
+
+    /// Every hash starting with `prefix`, as `SUFFIX:COUNT` lines, so a client can check a
+    /// password while only revealing the first few hex digits of its hash
+    pub fn range(&self, prefix: &str) -> Result<String, InputError> {
+        if prefix.len() != PREFIX_LENGTH || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(InputError {
+                message: "A hash prefix is 5 hex digits",
+            });
+        }
+        let prefix = u32::from_str_radix(prefix, 16)?;
+        // The first 20 bits of a hash, as 5 hex digits
+        let key = |hash: &Hash| u32::from_be_bytes([0, hash[0], hash[1], hash[2]]) >> 4;
+        let start = self.hashes.partition_point(|(hash, _)| key(hash) < prefix);
+
+        Ok(self.hashes[start..]
+            .iter()
+            .take_while(|(hash, _)| key(hash) == prefix)
+            .map(|(hash, count)| format!("{}:{count}\r\n", &HEXUPPER.encode(hash)[PREFIX_LENGTH..]))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Breaches;
+
+    #[test]
+    fn breaches_test() {
+        // SHA-1 of "password" and "hunter2", and a made up neighbour of the first
+        let breaches = Breaches::parse(
+            "# comment\n\
+             5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:3861493\n\
+             F3BBBD66A63D4BF1747940578EC3D0103530E21D\n\
+             5BAA6FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:2\n\
+             5BAA700000000000000000000000000000000000:1\n",
+        )
+        .unwrap();
+
+        assert_eq!(3_861_493, breaches.count("password"));
+        assert_eq!(1, breaches.count("hunter2"));
+        assert_eq!(0, breaches.count("correct horse battery staple"));
+        assert_eq!(
+            "1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\r\nFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:2\r\n",
+            breaches.range("5baa6").unwrap()
+        );
+        assert_eq!("", breaches.range("00000").unwrap());
+        assert!(breaches.range("5BAA").is_err());
+        assert!(breaches.range("5BAAG").is_err());
+
+        let breaches = Breaches::parse(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:5\n\
+             5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:2\n\
+             5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8\n",
+        )
+        .unwrap();
+        assert_eq!(8, breaches.count("password"));
+
+        assert!(Breaches::parse("5BAA6:1").is_err());
+        assert!(Breaches::parse("5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:lots").is_err());
+    }
+}
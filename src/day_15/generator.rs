@@ -0,0 +1,308 @@
+//! Finds passwords for a rule set: most rules can be met by adding a piece for each, and
+//! anything left over, like a hash suffix, by trying nonces until the password passes
+
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+
+use super::{Breaches, Rule, RuleSet, Text};
+use crate::common::{ApiError, Error, InputError};
+
+/// Random letters every generated password gets, on top of what its rules ask for
+const RANDOM_LETTERS: usize = 12;
+pub const DEFAULT_BUDGET: u32 = 100_000;
+pub const MAX_BUDGET: u32 = 1_000_000;
+/// Longest password the rules can ask for directly before we refuse to build one
+const MAX_BASE_LENGTH: usize = 1024;
+/// Longest nonce tried. With few free letters nonces grow quickly, and every attempt past this
+/// would only be longer.
+const MAX_NONCE_LENGTH: usize = 16;
+/// How long a search can run whatever its budget, since some rules are slow to check
+const MAX_SEARCH_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Generated {
+    password: String,
+    /// How many candidates were tried before one passed
+    attempts: u32,
+}
+
+struct Random(SystemRandom);
+
+impl Random {
+    /// A uniformly random index below `len`
+    fn index(&self, len: usize) -> Result<usize, Error> {
+        let len = len as u64;
+        // Drop the top of the range that doesn't divide evenly, so no index is more likely
+        let limit = u64::MAX - u64::MAX % len;
+        loop {
+            let mut bytes = [0; 8];
+            self.0.fill(&mut bytes).map_err(|_| Error {
+                message: "Couldn't generate random bytes",
+            })?;
+            let n = u64::from_le_bytes(bytes);
+            if n < limit {
+                return Ok((n % len) as usize);
+            }
+        }
+    }
+
+    fn pick<T: Copy>(&self, from: &[T]) -> Result<T, Error> {
+        Ok(from[self.index(from.len())?])
+    }
+}
+
+/// The letters that can go anywhere without breaking a rule: none from a word that must appear
+/// only once, or from a substring that mustn't appear at all
+fn free_letters(rules: &[&Rule]) -> Vec<char> {
+    let blocked: String = rules
+        .iter()
+        .flat_map(|rule| match rule {
+            Rule::OrderedOnce { word } => vec![word.clone()],
+            Rule::ForbiddenSubstrings { substrings } => substrings.clone(),
+            _ => Vec::new(),
+        })
+        .collect();
+    ('a'..='z')
+        .chain('A'..='Z')
+        .filter(|c| !blocked.contains(*c))
+        .collect()
+}
+
+/// Roughly how many bytes `base` would need for the rules, an overestimate if anything
+fn requested_length(rules: &[&Rule]) -> usize {
+    rules
+        .iter()
+        .map(|rule| match rule {
+            Rule::OrderedOnce { word } => word.len(),
+            Rule::MinLength { min } | Rule::MinDigits { min } => *min,
+            Rule::MinVowels { min, vowels } => min.saturating_mul(vowels.len().saturating_add(1)),
+            Rule::Sandwich { min } => min.saturating_mul(3),
+            // A few characters at most
+            _ => 8,
+        })
+        .fold(RANDOM_LETTERS, usize::saturating_add)
+}
+
+/// Builds the part of a password that the rules ask for directly
+fn base(rules: &[&Rule], letters: &[char], random: &Random) -> Result<String, Error> {
+    let lower: Vec<char> = letters
+        .iter()
+        .copied()
+        .filter(char::is_ascii_lowercase)
+        .collect();
+    let upper: Vec<char> = letters
+        .iter()
+        .copied()
+        .filter(char::is_ascii_uppercase)
+        .collect();
+    let any = |from: &[char]| -> Result<char, Error> {
+        random.pick(if from.is_empty() { letters } else { from })
+    };
+
+    let mut password = String::new();
+    let mut min_digits = 0;
+    let mut digit_sum = None;
+    let mut min_length = 0;
+    for rule in rules {
+        match rule {
+            Rule::OrderedOnce { word } => password.push_str(word),
+            Rule::MinLength { min } => min_length = min_length.max(*min),
+            Rule::MinDigits { min } => min_digits = min_digits.max(*min),
+            Rule::DigitSum { sum } => digit_sum = Some(*sum),
+            Rule::CharacterClasses {
+                upper: needs_upper,
+                lower: needs_lower,
+                digit,
+            } => {
+                if *needs_upper {
+                    password.push(any(&upper)?);
+                }
+                if *needs_lower {
+                    password.push(any(&lower)?);
+                }
+                if *digit {
+                    min_digits = min_digits.max(1);
+                }
+            }
+            Rule::MinVowels { min, vowels } => {
+                let vowels = Text::graphemes(vowels);
+                let free: Vec<&String> = vowels
+                    .iter()
+                    .filter(|v| v.chars().all(|c| letters.contains(&c)))
+                    .collect();
+                // A vowel that's blocked is still better than none at all
+                let Some(vowel) = free.first().copied().or(vowels.first()) else {
+                    continue;
+                };
+                // Spaced out by free letters, in case a doubled vowel is forbidden
+                for _ in 0..*min {
+                    password.push_str(vowel);
+                    password.push(any(letters)?);
+                }
+            }
+            Rule::RepeatedLetter => {
+                let letter = any(letters)?;
+                password.extend([letter, letter]);
+            }
+            Rule::Sandwich { min } => {
+                let outer = any(letters)?;
+                let inner = any(&letters
+                    .iter()
+                    .copied()
+                    .filter(|c| *c != outer)
+                    .collect::<Vec<_>>())?;
+                for _ in 0..*min {
+                    password.extend([outer, inner, outer]);
+                }
+            }
+            Rule::CharRange { start, .. } => password.push(*start),
+            Rule::Emoji => password.push('😀'),
+            Rule::ForbiddenSubstrings { .. }
+            | Rule::Sha256Suffix { .. }
+            | Rule::MinStrength { .. }
+            | Rule::NotBreached => {}
+        }
+    }
+
+    // All the digits go in one run, so a required sum is just that run's value
+    let digits = match digit_sum {
+        Some(sum) => format!("{sum:0>min_digits$}"),
+        None => (0..min_digits)
+            .map(|_| random.pick(&['0', '1', '2', '3', '4', '5', '6', '7', '8', '9']))
+            .collect::<Result<_, _>>()?,
+    };
+    password.push_str(&digits);
+
+    for _ in 0..RANDOM_LETTERS {
+        password.push(random.pick(letters)?);
+    }
+    while Text::new(&password).graphemes.len() < min_length {
+        password.push(random.pick(letters)?);
+    }
+    Ok(password)
+}
+
+/// Spells out `n` in the free letters, to vary a password without breaking its rules
+fn nonce(mut n: u32, letters: &[char]) -> String {
+    let mut nonce = String::new();
+    while n > 0 {
+        n -= 1;
+        nonce.push(letters[n as usize % letters.len()]);
+        n /= letters.len() as u32;
+    }
+    nonce
+}
+
+/// Looks for a password passing every rule in `set`, trying at most `budget` candidates
+pub fn generate(set: &RuleSet, budget: u32, breaches: &Breaches) -> Result<Generated, ApiError> {
+    if budget > MAX_BUDGET {
+        return Err(InputError {
+            message: "The search budget can be at most 1000000 attempts",
+        }
+        .into());
+    }
+
+    // Estimating strength is by far the slowest check, so leave it for candidates that pass
+    // everything else
+    let mut rules: Vec<&Rule> = set.rules.iter().map(|spec| &spec.rule).collect();
+    rules.sort_by_key(|rule| matches!(rule, Rule::MinStrength { .. }));
+
+    let letters = free_letters(&rules);
+    if letters.is_empty() {
+        return Err(InputError {
+            message: "The rules leave no letters free to build a password from",
+        }
+        .into());
+    }
+    if requested_length(&rules) > MAX_BASE_LENGTH {
+        return Err(InputError {
+            message: "The rules ask for a longer password than can be generated",
+        }
+        .into());
+    }
+    let base = base(&rules, &letters, &Random(SystemRandom::new()))?;
+
+    let deadline = Instant::now() + MAX_SEARCH_TIME;
+    for attempt in 0..budget {
+        let nonce = nonce(attempt, &letters);
+        if nonce.len() > MAX_NONCE_LENGTH || Instant::now() > deadline {
+            break;
+        }
+        let password: String = (base.clone() + &nonce).nfc().collect();
+        let input = Text::new(&password);
+        if rules.iter().all(|rule| rule.passes(&input, breaches)) {
+            return Ok(Generated {
+                password,
+                attempts: attempt + 1,
+            });
+        }
+    }
+    Err(InputError {
+        message: "No password passing every rule was found within the search budget",
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nonce;
+
+    #[test]
+    fn nonce_test() {
+        let letters = ['x', 'y'];
+        let nonces: Vec<_> = (0..7).map(|n| nonce(n, &letters)).collect();
+        assert_eq!(vec!["", "x", "y", "xx", "yx", "xy", "yy"], nonces);
+    }
+
+    #[test]
+    fn bounded_search_test() {
+        use std::time::Instant;
+
+        use super::{generate, Breaches, Rule, RuleSet, MAX_SEARCH_TIME};
+        use crate::common::{ApiError, InputError};
+        use crate::day_15::RuleSpec;
+
+        let rule_set = |rules: Vec<Rule>| RuleSet {
+            rules: rules
+                .into_iter()
+                .map(|rule| RuleSpec::new(rule, rocket::http::Status::BadRequest, None))
+                .collect(),
+            success_reason: None,
+        };
+        let message = |result| match result {
+            Err(ApiError::Input(InputError { message })) => message,
+            _ => panic!("expected an input error"),
+        };
+
+        // Only `x` is free, and the ordered word is made of a forbidden letter so no password
+        // can pass. The nonces are all `x`s, one longer each attempt, so without a cap they
+        // would grow to a million letters.
+        let blocked = ('a'..='z')
+            .chain('A'..='Z')
+            .filter(|c| *c != 'x')
+            .map(String::from)
+            .collect();
+        let set = rule_set(vec![
+            Rule::ForbiddenSubstrings {
+                substrings: blocked,
+            },
+            Rule::OrderedOnce { word: "a".into() },
+        ]);
+        let start = Instant::now();
+        assert_eq!(
+            "No password passing every rule was found within the search budget",
+            message(generate(&set, 1_000_000, &Breaches::default()))
+        );
+        assert!(start.elapsed() < MAX_SEARCH_TIME);
+
+        let set = rule_set(vec![Rule::MinLength { min: usize::MAX }]);
+        assert_eq!(
+            "The rules ask for a longer password than can be generated",
+            message(generate(&set, 1, &Breaches::default()))
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! Password strength estimation in the style of zxcvbn: find the guessable patterns in a
+//! password, then work out the cheapest way an attacker could guess it as a run of them
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use chrono::{Datelike, Utc};
+use rocket::serde::Serialize;
+
+/// Passwords longer than this are estimated from their start, the rest only makes them stronger
+const MAX_ANALYSED_CHARS: usize = 100;
+/// Guesses per character for anything that doesn't fit a pattern
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+/// Extra guesses for every pattern after the first, so splitting into many tiny patterns
+/// doesn't look cheaper than it is
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 10_000.0;
+const MAX_WORD_LENGTH: usize = 20;
+
+/// A count as a float. Counts here are of characters or dictionary ranks, far below where `f64`
+/// would lose precision, and anything past `u32::MAX` is as good as infinite anyway.
+fn float(n: usize) -> f64 {
+    f64::from(u32::try_from(n).unwrap_or(u32::MAX))
+}
+
+/// A count as an exponent for `powi`, where anything too big is as good as infinite
+fn exponent(n: usize) -> i32 {
+    i32::try_from(n).unwrap_or(i32::MAX)
+}
+
+/// Bundled lists of common passwords and words, most common first
+const WORD_LISTS: [(&str, &str); 2] = [
+    ("passwords", include_str!("../../wordlists/passwords.txt")),
+    ("english", include_str!("../../wordlists/english.txt")),
+];
+
+struct Dictionary {
+    name: &'static str,
+    ranks: HashMap<&'static str, usize>,
+}
+
+fn dictionaries() -> &'static [Dictionary] {
+    static DICTIONARIES: OnceLock<Vec<Dictionary>> = OnceLock::new();
+    DICTIONARIES.get_or_init(|| {
+        WORD_LISTS
+            .iter()
+            .map(|(name, list)| Dictionary {
+                name,
+                ranks: list
+                    .lines()
+                    .map(str::trim)
+                    .filter(|word| !word.is_empty())
+                    .enumerate()
+                    .map(|(i, word)| (word, i + 1))
+                    .collect(),
+            })
+            .collect()
+    })
+}
+
+/// Keyboard rows with how far each is shifted to the right in quarter keys, for working out
+/// neighbouring keys
+const QWERTY: [(&str, &str, i8); 4] = [
+    ("`1234567890-=", "~!@#$%^&*()_+", 0),
+    ("qwertyuiop[]\\", "QWERTYUIOP{}|", 2),
+    ("asdfghjkl;'", "ASDFGHJKL:\"", 3),
+    ("zxcvbnm,./", "ZXCVBNM<>?", 5),
+];
+const KEYBOARD_STARTING_POSITIONS: f64 = 47.0;
+const KEYBOARD_AVERAGE_DEGREE: f64 = 4.595;
+
+/// Where a key is on the keyboard, as its row and how far along it is in quarter keys, and
+/// whether it needs shift
+fn key_position(c: char) -> Option<(i8, i8, bool)> {
+    QWERTY
+        .iter()
+        .zip(0..)
+        .find_map(|((plain, shifted, offset), row)| {
+            let found = |keys: &str| {
+                let col = keys.chars().position(|k| k == c)?;
+                Some(i8::try_from(col).ok()? * 4 + offset)
+            };
+            found(plain)
+                .map(|x| (row, x, false))
+                .or_else(|| found(shifted).map(|x| (row, x, true)))
+        })
+}
+
+fn adjacent(a: char, b: char) -> Option<(i8, i8)> {
+    let (row_a, x_a, _) = key_position(a)?;
+    let (row_b, x_b, _) = key_position(b)?;
+    let (d_row, d_x) = (row_b - row_a, x_b - x_a);
+    let neighbours = (d_row == 0 && d_x.abs() == 4) || (d_row.abs() == 1 && d_x.abs() <= 4);
+    neighbours.then(|| (d_row.signum(), d_x.signum()))
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "pattern", rename_all = "snake_case")]
+enum Pattern {
+    Dictionary {
+        dictionary: &'static str,
+        word: String,
+        rank: usize,
+        reversed: bool,
+        l33t: bool,
+    },
+    KeyboardWalk {
+        turns: usize,
+    },
+    Repeat {
+        base: String,
+        count: usize,
+    },
+    Sequence {
+        ascending: bool,
+    },
+    Date {
+        year: i32,
+    },
+    Bruteforce,
+}
+
+/// A guessable part of a password
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Match {
+    #[serde(flatten)]
+    pattern: Pattern,
+    token: String,
+    guesses: f64,
+    #[serde(skip)]
+    start: usize,
+    /// Exclusive
+    #[serde(skip)]
+    end: usize,
+}
+
+impl Match {
+    fn new(chars: &[char], start: usize, end: usize, pattern: Pattern, guesses: f64) -> Self {
+        Self {
+            pattern,
+            token: chars[start..end].iter().collect(),
+            guesses: guesses.max(1.0),
+            start,
+            end,
+        }
+    }
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    (0..k).fold(1.0, |acc, i| acc * float(n - i) / float(i + 1))
+}
+
+/// How many ways the letters' case could have been chosen, given the common ones are tried first
+fn case_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+    let first_only = token.first().is_some_and(|c| c.is_uppercase()) && upper == 1;
+    let last_only = token.last().is_some_and(|c| c.is_uppercase()) && upper == 1;
+    if lower == 0 || first_only || last_only {
+        return 2.0;
+    }
+    (1..=upper.min(lower))
+        .map(|k| binomial(upper + lower, k))
+        .sum()
+}
+
+fn unl33t(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        _ => c,
+    }
+}
+
+fn dictionary_matches(chars: &[char]) -> Vec<Match> {
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    let mut matches = Vec::new();
+
+    for start in 0..lower.len() {
+        for end in start + 3..=lower.len().min(start + MAX_WORD_LENGTH) {
+            let token = &lower[start..end];
+            let plain: String = token.iter().collect();
+            let unl33ted: String = token.iter().map(|c| unl33t(*c)).collect();
+            let reversed: String = token.iter().rev().collect();
+            let candidates = [
+                (&plain, false, false),
+                (&unl33ted, false, true),
+                (&reversed, true, false),
+            ];
+
+            for dictionary in dictionaries() {
+                let best = candidates
+                    .iter()
+                    .filter(|(_, _, l33t)| !l33t || unl33ted != plain)
+                    .filter_map(|(word, reversed, l33t)| {
+                        let rank = *dictionary.ranks.get(word.as_str())?;
+                        let mut guesses = float(rank) * case_variations(&chars[start..end]);
+                        if *reversed {
+                            guesses *= 2.0;
+                        }
+                        if *l33t {
+                            guesses *= 2.0;
+                        }
+                        Some((guesses, (*word).clone(), rank, *reversed, *l33t))
+                    })
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+                if let Some((guesses, word, rank, reversed, l33t)) = best {
+                    let pattern = Pattern::Dictionary {
+                        dictionary: dictionary.name,
+                        word,
+                        rank,
+                        reversed,
+                        l33t,
+                    };
+                    matches.push(Match::new(chars, start, end, pattern, guesses));
+                }
+            }
+        }
+    }
+    matches
+}
+
+fn keyboard_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut direction = None;
+        while end < chars.len() {
+            let Some(step) = adjacent(chars[end - 1], chars[end]) else {
+                break;
+            };
+            if direction != Some(step) {
+                turns += 1;
+                direction = Some(step);
+            }
+            end += 1;
+        }
+
+        let len = end - start;
+        if len >= 3 {
+            let mut guesses = 0.0;
+            for i in 2..=len {
+                for j in 1..=turns.min(i - 1) {
+                    guesses += binomial(i - 1, j - 1)
+                        * KEYBOARD_STARTING_POSITIONS
+                        * KEYBOARD_AVERAGE_DEGREE.powi(exponent(j));
+                }
+            }
+            let token = &chars[start..end];
+            let shifted = token
+                .iter()
+                .filter(|c| key_position(**c).is_some_and(|(_, _, shifted)| shifted))
+                .count();
+            let unshifted = len - shifted;
+            if shifted > 0 {
+                guesses *= if unshifted == 0 {
+                    2.0
+                } else {
+                    (1..=shifted.min(unshifted)).map(|k| binomial(len, k)).sum()
+                };
+            }
+            let pattern = Pattern::KeyboardWalk { turns };
+            matches.push(Match::new(chars, start, end, pattern, guesses));
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+fn repeat_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        // The longest stretch of one block repeated from here
+        let best = (1..=(chars.len() - start) / 2)
+            .map(|block| {
+                let base = &chars[start..start + block];
+                let count = chars[start..]
+                    .chunks_exact(block)
+                    .take_while(|chunk| *chunk == base)
+                    .count();
+                (block, count)
+            })
+            .filter(|(_, count)| *count >= 2)
+            .max_by_key(|(block, count)| block * count);
+
+        if let Some((block, count)) = best {
+            let base: String = chars[start..start + block].iter().collect();
+            let guesses = estimate_guesses(&base) * float(count);
+            let end = start + block * count;
+            let pattern = Pattern::Repeat { base, count };
+            matches.push(Match::new(chars, start, end, pattern, guesses));
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let class = |c: char| {
+        if c.is_ascii_lowercase() {
+            Some(0)
+        } else if c.is_ascii_uppercase() {
+            Some(1)
+        } else if c.is_ascii_digit() {
+            Some(2)
+        } else {
+            None
+        }
+    };
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + 1 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let same_class = |a: char, b: char| class(a).is_some() && class(a) == class(b);
+        if delta == 0 || delta.abs() > 5 || !same_class(chars[start], chars[start + 1]) {
+            start += 1;
+            continue;
+        }
+
+        let mut end = start + 2;
+        while end < chars.len()
+            && chars[end] as i64 - chars[end - 1] as i64 == delta
+            && same_class(chars[start], chars[end])
+        {
+            end += 1;
+        }
+
+        if end - start >= 3 {
+            let first = chars[start];
+            let base = if "aAzZ01".contains(first) || first == '9' {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let ascending = delta > 0;
+            let guesses = base * float(end - start) * if ascending { 1.0 } else { 2.0 };
+            matches.push(Match::new(
+                chars,
+                start,
+                end,
+                Pattern::Sequence { ascending },
+                guesses,
+            ));
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+/// Reads a run of digits, possibly split by one kind of separator, as a day, month and year
+fn parse_date(token: &[char]) -> Option<i32> {
+    let separators = ['/', '-', '.', ' ', '_'];
+    let separator = token.iter().find(|c| separators.contains(c));
+    let groups: Vec<String> = if let Some(sep) = separator {
+        token
+            .split(|c| c == sep)
+            .map(|group| group.iter().collect())
+            .collect()
+    } else {
+        let digits: String = token.iter().collect();
+        match digits.len() {
+            4 => vec![digits],
+            6 => vec![
+                digits[0..2].into(),
+                digits[2..4].into(),
+                digits[4..6].into(),
+            ],
+            8 => {
+                // Both ddmmyyyy and yyyymmdd are common
+                let year_last = [&digits[0..2], &digits[2..4], &digits[4..8]];
+                let year_first = [&digits[0..4], &digits[4..6], &digits[6..8]];
+                return [year_last, year_first]
+                    .iter()
+                    .find_map(|parts| date_from_parts(parts));
+            }
+            _ => return None,
+        }
+    };
+
+    if groups.len() == 1 {
+        let year: i32 = groups[0].parse().ok()?;
+        return (1900..=2050).contains(&year).then_some(year);
+    }
+    let parts: Vec<&str> = groups.iter().map(String::as_str).collect();
+    date_from_parts(&parts)
+}
+
+fn date_from_parts(parts: &[&str]) -> Option<i32> {
+    if parts.len() != 3 || parts.iter().any(|p| p.is_empty() || p.len() > 4) {
+        return None;
+    }
+    let numbers: Vec<i32> = parts
+        .iter()
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    let plausible_day_month = |a: i32, b: i32| {
+        ((1..=31).contains(&a) && (1..=12).contains(&b))
+            || ((1..=12).contains(&a) && (1..=31).contains(&b))
+    };
+    let full_year = |y: i32, digits: usize| match digits {
+        2 if y > 50 => Some(1900 + y),
+        2 => Some(2000 + y),
+        4 if (1000..=2050).contains(&y) => Some(y),
+        _ => None,
+    };
+
+    // Year last, then year first
+    if plausible_day_month(numbers[0], numbers[1]) {
+        if let Some(year) = full_year(numbers[2], parts[2].len()) {
+            return Some(year);
+        }
+    }
+    if plausible_day_month(numbers[1], numbers[2]) {
+        return full_year(numbers[0], parts[0].len());
+    }
+    None
+}
+
+fn date_matches(chars: &[char]) -> Vec<Match> {
+    let this_year = Utc::now().year();
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for end in start + 4..=chars.len().min(start + 10) {
+            let token = &chars[start..end];
+            let digits_at_ends =
+                token[0].is_ascii_digit() && token[token.len() - 1].is_ascii_digit();
+            if !digits_at_ends
+                || !token
+                    .iter()
+                    .all(|c| c.is_ascii_digit() || "/-. _".contains(*c))
+            {
+                continue;
+            }
+            if let Some(year) = parse_date(token) {
+                let years_away = f64::from((year - this_year).abs().max(20));
+                let mut guesses = if token.len() == 4 {
+                    years_away
+                } else {
+                    years_away * 365.0
+                };
+                if token.iter().any(|c| !c.is_ascii_digit()) {
+                    guesses *= 4.0;
+                }
+                matches.push(Match::new(
+                    chars,
+                    start,
+                    end,
+                    Pattern::Date { year },
+                    guesses,
+                ));
+            }
+        }
+    }
+    matches
+}
+
+fn factorial(n: usize) -> f64 {
+    (1..=n).map(float).product()
+}
+
+/// The guesses needed for a run of matches, and the matches
+type Cover = (f64, Vec<Match>);
+
+/// The cheapest way to cover the whole password with matches and brute force
+fn cheapest_cover(chars: &[char], matches: &[Match]) -> Cover {
+    let n = chars.len();
+    if n == 0 {
+        return (1.0, Vec::new());
+    }
+
+    // best[end][count]: fewest guesses for the first `end` chars as `count` parts
+    let mut best: Vec<Vec<Option<Cover>>> = vec![vec![None; n + 1]; n + 1];
+    best[0][0] = Some((1.0, Vec::new()));
+    let mut by_end: Vec<Vec<Match>> = vec![Vec::new(); n + 1];
+    for m in matches {
+        by_end[m.end].push(m.clone());
+    }
+    for (end, ending_here) in by_end.iter().enumerate().skip(1) {
+        for start in 0..end {
+            let bruteforce = Match::new(
+                chars,
+                start,
+                end,
+                Pattern::Bruteforce,
+                BRUTEFORCE_CARDINALITY.powi(exponent(end - start)),
+            );
+            let candidates = ending_here
+                .iter()
+                .filter(|m| m.start == start)
+                .chain(std::iter::once(&bruteforce));
+            let (done, rest) = best.split_at_mut(end);
+            for candidate in candidates {
+                for count in 0..end {
+                    let Some((guesses, parts)) = &done[start][count] else {
+                        continue;
+                    };
+                    // Two bruteforce stretches in a row are really one
+                    if matches!(candidate.pattern, Pattern::Bruteforce)
+                        && parts
+                            .last()
+                            .is_some_and(|p| matches!(p.pattern, Pattern::Bruteforce))
+                    {
+                        continue;
+                    }
+                    let total = guesses * candidate.guesses;
+                    let slot = &mut rest[0][count + 1];
+                    if slot.as_ref().is_none_or(|(g, _)| total < *g) {
+                        let mut parts = parts.clone();
+                        parts.push(candidate.clone());
+                        *slot = Some((total, parts));
+                    }
+                }
+            }
+        }
+    }
+
+    best[n]
+        .iter()
+        .enumerate()
+        .filter_map(|(count, entry)| {
+            let (guesses, parts) = entry.as_ref()?;
+            let total = factorial(count) * guesses
+                + MIN_GUESSES_BEFORE_GROWING_SEQUENCE.powi(exponent(count) - 1);
+            Some((total, parts.clone()))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap_or((1.0, Vec::new()))
+}
+
+fn analyse(password: &str) -> (f64, Vec<Match>) {
+    let chars: Vec<char> = password.chars().collect();
+    let (analysed, rest) = chars.split_at(chars.len().min(MAX_ANALYSED_CHARS));
+
+    let mut matches = dictionary_matches(analysed);
+    matches.extend(keyboard_matches(analysed));
+    matches.extend(repeat_matches(analysed));
+    matches.extend(sequence_matches(analysed));
+    matches.extend(date_matches(analysed));
+
+    let (guesses, parts) = cheapest_cover(analysed, &matches);
+    (
+        guesses * BRUTEFORCE_CARDINALITY.powi(exponent(rest.len())),
+        parts,
+    )
+}
+
+fn estimate_guesses(password: &str) -> f64 {
+    analyse(password).0
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CrackTime {
+    seconds: f64,
+    display: String,
+}
+
+impl CrackTime {
+    fn new(guesses: f64, per_second: f64) -> Self {
+        const MINUTE: f64 = 60.0;
+        const HOUR: f64 = MINUTE * 60.0;
+        const DAY: f64 = HOUR * 24.0;
+        const MONTH: f64 = DAY * 31.0;
+        const YEAR: f64 = MONTH * 12.0;
+        const CENTURY: f64 = YEAR * 100.0;
+
+        let seconds = guesses / per_second;
+
+        let display = if seconds < 1.0 {
+            "less than a second".to_string()
+        } else if seconds >= CENTURY {
+            "centuries".to_string()
+        } else {
+            let (amount, unit) = [
+                (YEAR, "year"),
+                (MONTH, "month"),
+                (DAY, "day"),
+                (HOUR, "hour"),
+                (MINUTE, "minute"),
+                (1.0, "second"),
+            ]
+            .into_iter()
+            .find(|(size, _)| seconds >= *size)
+            .map_or((seconds.round(), "second"), |(size, unit)| {
+                ((seconds / size).round(), unit)
+            });
+            // Rounded, so exactly 1 when it should read as one
+            #[allow(clippy::float_cmp)]
+            let plural = if amount == 1.0 { "" } else { "s" };
+            format!("{amount} {unit}{plural}")
+        };
+        Self { seconds, display }
+    }
+}
+
+/// How long guessing would take against different kinds of attack
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CrackTimes {
+    /// A login form with rate limiting
+    online_throttled: CrackTime,
+    online_unthrottled: CrackTime,
+    /// A stolen database of slow hashes like bcrypt
+    offline_slow_hash: CrackTime,
+    /// A stolen database of fast hashes on a rig of GPUs
+    offline_fast_hash: CrackTime,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Estimate {
+    /// From 0, guessable in under a thousand tries, to 4, needing over ten billion
+    pub score: u8,
+    guesses: f64,
+    guesses_log10: f64,
+    crack_time: CrackTimes,
+    patterns: Vec<Match>,
+    suggestions: Vec<&'static str>,
+}
+
+pub fn estimate(password: &str) -> Estimate {
+    let (guesses, patterns) = analyse(password);
+    let score = match guesses {
+        g if g < 1e3 + 5.0 => 0,
+        g if g < 1e6 + 5.0 => 1,
+        g if g < 1e8 + 5.0 => 2,
+        g if g < 1e10 + 5.0 => 3,
+        _ => 4,
+    };
+
+    let mut suggestions = Vec::new();
+    let mut suggest = |suggestion| {
+        if !suggestions.contains(&suggestion) {
+            suggestions.push(suggestion);
+        }
+    };
+    for part in &patterns {
+        match &part.pattern {
+            Pattern::Dictionary {
+                dictionary,
+                reversed,
+                l33t,
+                ..
+            } => {
+                suggest(if *dictionary == "passwords" {
+                    "Avoid passwords that appear on common password lists"
+                } else {
+                    "Single common words are easy to guess, combine several unrelated ones"
+                });
+                let token: Vec<char> = part.token.chars().collect();
+                // Exactly 2 is what only capitalising the first, last or every letter gives
+                #[allow(clippy::float_cmp)]
+                let simply_capitalised = case_variations(&token) == 2.0;
+                if simply_capitalised {
+                    suggest("Capitalising the first or every letter doesn't help much");
+                }
+                if *reversed {
+                    suggest("Reversed words aren't much harder to guess");
+                }
+                if *l33t {
+                    suggest("Predictable substitutions like '@' for 'a' don't help much");
+                }
+            }
+            Pattern::KeyboardWalk { .. } => suggest("Avoid keyboard patterns like 'qwerty'"),
+            Pattern::Repeat { .. } => suggest("Avoid repeated words and characters"),
+            Pattern::Sequence { .. } => suggest("Avoid sequences like 'abc' or '6543'"),
+            Pattern::Date { .. } => suggest("Avoid dates and years that are associated with you"),
+            Pattern::Bruteforce => {}
+        }
+    }
+    if score < 3 {
+        suggest("Add another word or two, uncommon words are better");
+    }
+
+    Estimate {
+        score,
+        guesses,
+        guesses_log10: guesses.log10(),
+        crack_time: CrackTimes {
+            online_throttled: CrackTime::new(guesses, 100.0 / 3600.0),
+            online_unthrottled: CrackTime::new(guesses, 10.0),
+            offline_slow_hash: CrackTime::new(guesses, 1e4),
+            offline_fast_hash: CrackTime::new(guesses, 1e10),
+        },
+        patterns,
+        suggestions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::estimate;
+
+    fn patterns(password: &str) -> Vec<String> {
+        estimate(password)
+            .patterns
+            .iter()
+            .map(|m| {
+                format!("{:?}", m.pattern)
+                    .split([' ', '{'])
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weak_passwords_test() {
+        for password in [
+            "password",
+            "P@ssw0rd",
+            "qwerty123",
+            "aaaaaaaa",
+            "abcdefg",
+            "12/05/1994",
+            "drowssap",
+        ] {
+            let estimate = estimate(password);
+            assert!(estimate.score <= 1, "{password} scored {}", estimate.score);
+            assert!(!estimate.suggestions.is_empty(), "{password}");
+        }
+    }
+
+    #[test]
+    fn patterns_test() {
+        assert_eq!(vec!["Dictionary"], patterns("Password"));
+        assert_eq!(vec!["KeyboardWalk"], patterns("zxcvbnm"));
+        assert_eq!(vec!["Repeat"], patterns("abcabcabc"));
+        assert_eq!(vec!["Sequence"], patterns("97531"));
+        assert_eq!(vec!["Date"], patterns("1994-05-12"));
+        assert_eq!(vec!["Dictionary", "Date"], patterns("santa2023"));
+    }
+
+    #[test]
+    fn strong_passwords_test() {
+        for password in ["correct horse battery staple", "vT8#qL2!mZ9@wR4$"] {
+            let estimate = estimate(password);
+            assert_eq!(4, estimate.score, "{password}");
+            assert_eq!("centuries", estimate.crack_time.offline_slow_hash.display);
+        }
+    }
+}
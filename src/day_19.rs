@@ -1,14 +1,18 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use chrono::{DateTime, SecondsFormat, Utc};
+use rocket::fairing::AdHoc;
 use rocket::futures::channel::mpsc::{self, Receiver, Sender};
 use rocket::futures::prelude::*;
-use rocket::serde::json::serde_json;
+use rocket::serde::json::{serde_json, Json};
 use rocket::serde::{Deserialize, Serialize};
 use rocket::{get, post, routes, Route};
 use rocket_ws as ws;
+use sqlx::{Executor, FromRow, SqlitePool};
 use ws::Message;
 
-use crate::common::Error;
+use crate::common::{config_fairing, ApiError, Error, InputError};
 
 #[get("/ws/ping")]
 fn ws_ping(ws: ws::WebSocket) -> ws::Stream![] {
@@ -30,26 +34,34 @@ fn ws_ping(ws: ws::WebSocket) -> ws::Stream![] {
 
 struct User {
     id: String,
-    rx: Receiver<RoomMessage>,
+    /// Tells apart several connections from the same user
+    connection: u64,
+    rx: Receiver<RoomEvent>,
 }
 
 impl User {
-    fn new(id: String) -> (Self, Sender<RoomMessage>) {
+    fn new(id: String, connection: u64) -> (Self, Sender<RoomEvent>) {
         let (tx, rx) = mpsc::channel(MSG_CHAR_LIMIT);
-        (Self { id, rx }, tx)
+        (Self { id, connection, rx }, tx)
     }
 }
 
 #[derive(Debug)]
 struct RoomUser {
     id: String,
+    connection: u64,
     room_id: u32,
-    tx: Sender<RoomMessage>,
+    tx: Sender<RoomEvent>,
 }
 
 impl RoomUser {
-    fn new(id: String, room_id: u32, tx: Sender<RoomMessage>) -> Self {
-        Self { id, room_id, tx }
+    fn new(user: &User, room_id: u32, tx: Sender<RoomEvent>) -> Self {
+        Self {
+            id: user.id.clone(),
+            connection: user.connection,
+            room_id,
+            tx,
+        }
     }
 }
 
@@ -57,6 +69,7 @@ impl RoomUser {
 struct State {
     views: u32,
     users: Vec<RoomUser>,
+    connections: u64,
 }
 
 #[derive(Deserialize)]
@@ -76,45 +89,116 @@ impl TryFrom<&str> for UserMessage {
 #[derive(Serialize, Clone, Debug)]
 #[serde(crate = "rocket::serde")]
 struct RoomMessage {
+    id: i64,
     #[serde(skip)]
     room_id: u32,
     user: String,
     message: String,
+    /// RFC 3339, in UTC
+    timestamp: String,
 }
 
 const MSG_CHAR_LIMIT: usize = 128;
 
-impl RoomMessage {
-    fn new(room_id: u32, user: String, message: String) -> Option<Self> {
-        if message.chars().count() > MSG_CHAR_LIMIT {
-            None
-        } else {
-            Some(Self {
-                room_id,
-                user,
-                message,
-            })
+/// A message as stored in `chat_messages`
+#[derive(FromRow)]
+struct StoredMessage {
+    id: i64,
+    room_id: u32,
+    user_id: String,
+    message: String,
+    /// Milliseconds since the Unix epoch
+    sent_at: i64,
+}
+
+impl From<StoredMessage> for RoomMessage {
+    fn from(stored: StoredMessage) -> Self {
+        let timestamp = DateTime::from_timestamp_millis(stored.sent_at)
+            .unwrap_or_default()
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        Self {
+            id: stored.id,
+            room_id: stored.room_id,
+            user: stored.user_id,
+            message: stored.message,
+            timestamp,
+        }
+    }
+}
+
+/// Someone arriving in or leaving a room
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+struct Presence {
+    user: String,
+    /// RFC 3339, in UTC
+    timestamp: String,
+}
+
+impl Presence {
+    fn new(user: String) -> Self {
+        Self {
+            user,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
         }
     }
 }
 
-impl From<RoomMessage> for Message {
-    fn from(value: RoomMessage) -> Self {
+/// Everything sent to a room's users, tagged with a `type` so clients can tell chat from
+/// system events
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+enum RoomEvent {
+    Message(RoomMessage),
+    Join(Presence),
+    Leave(Presence),
+}
+
+impl From<RoomEvent> for Message {
+    fn from(value: RoomEvent) -> Self {
         Message::text(
             serde_json::to_string(&value).expect("JSON serialization failed for some reason"),
         )
     }
 }
 
+/// How much history chat rooms keep around
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ChatConfig {
+    /// How many of a room's latest messages are sent to someone joining it
+    replay: u32,
+    /// The most messages a page of history can have
+    max_page_size: u32,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            replay: 20,
+            max_page_size: 100,
+        }
+    }
+}
+
+impl ChatConfig {
+    pub fn fairing() -> AdHoc {
+        config_fairing::<Self>("Chat", "chat")
+    }
+}
+
 pub struct ChatState {
     state: RwLock<State>,
+    pool: SqlitePool,
 }
 
 impl ChatState {
-    pub fn new() -> Self {
-        Self {
+    pub async fn new(pool: SqlitePool) -> Result<Self, Error> {
+        pool.execute(include_str!("../db/schema_19.sql")).await?;
+        Ok(Self {
             state: RwLock::new(State::default()),
-        }
+            pool,
+        })
     }
 
     fn read(&self) -> Result<RwLockReadGuard<'_, State>, Error> {
@@ -126,12 +210,148 @@ impl ChatState {
         let state = self.state.write()?;
         Ok(state)
     }
+
+    /// Adds a connection to a room, returning its end of the room and whether the user wasn't
+    /// already there on another connection
+    fn join(&self, room_id: u32, user_id: &str) -> Result<(User, bool), Error> {
+        let mut state = self.write()?;
+        state.connections += 1;
+        let (user, tx) = User::new(user_id.to_string(), state.connections);
+        let arrived = !state
+            .users
+            .iter()
+            .any(|u| u.room_id == room_id && u.id == user.id);
+        state.users.push(RoomUser::new(&user, room_id, tx));
+        Ok((user, arrived))
+    }
+
+    /// Removes a connection from its room, returning whether it was the user's last one there
+    fn leave(&self, room_id: u32, user_id: &str, connection: u64) -> Result<bool, Error> {
+        let mut state = self.write()?;
+        state.users.retain(|u| u.connection != connection);
+        Ok(!state
+            .users
+            .iter()
+            .any(|u| u.room_id == room_id && u.id == user_id))
+    }
+
+    async fn broadcast(&self, room_id: u32, event: RoomEvent) -> Result<(), Error> {
+        let txs: Vec<_> = self
+            .read()?
+            .users
+            .iter()
+            .filter(|u| u.room_id == room_id)
+            .map(|u| u.tx.clone())
+            .collect();
+        for mut tx in txs {
+            let _ = tx.send(event.clone()).await;
+        }
+        Ok(())
+    }
+
+    /// Rooms with anyone in them, and how many different users each has
+    fn rooms(&self) -> Result<Vec<RoomSummary>, Error> {
+        let state = self.read()?;
+        let mut rooms: BTreeMap<u32, BTreeSet<&str>> = BTreeMap::new();
+        for user in &state.users {
+            rooms.entry(user.room_id).or_default().insert(&user.id);
+        }
+        Ok(rooms
+            .into_iter()
+            .map(|(room_id, users)| RoomSummary {
+                room_id,
+                users: users.len(),
+            })
+            .collect())
+    }
+
+    fn users(&self, room_id: u32) -> Result<Vec<String>, Error> {
+        let state = self.read()?;
+        let users: BTreeSet<&str> = state
+            .users
+            .iter()
+            .filter(|u| u.room_id == room_id)
+            .map(|u| u.id.as_str())
+            .collect();
+        Ok(users.into_iter().map(Into::into).collect())
+    }
+
+    /// Stores a message, or returns `None` if it's too long to send
+    async fn save(
+        &self,
+        room_id: u32,
+        user: String,
+        message: String,
+    ) -> Result<Option<RoomMessage>, Error> {
+        if message.chars().count() > MSG_CHAR_LIMIT {
+            return Ok(None);
+        }
+        let sent_at = Utc::now().timestamp_millis();
+        let id = sqlx::query(
+            "INSERT INTO chat_messages (room_id, user_id, message, sent_at)
+            VALUES ($1, $2, $3, $4)",
+        )
+        .bind(room_id)
+        .bind(&user)
+        .bind(&message)
+        .bind(sent_at)
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
+
+        Ok(Some(
+            StoredMessage {
+                id,
+                room_id,
+                user_id: user,
+                message,
+                sent_at,
+            }
+            .into(),
+        ))
+    }
+
+    /// Up to `limit` of a room's messages from before the one with id `before`, oldest first
+    async fn history(
+        &self,
+        room_id: u32,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<RoomMessage>, Error> {
+        let mut messages: Vec<StoredMessage> = sqlx::query_as(
+            "SELECT id, room_id, user_id, message, sent_at FROM chat_messages
+            WHERE room_id = $1 AND id < $2
+            ORDER BY id DESC
+            LIMIT $3",
+        )
+        .bind(room_id)
+        .bind(before.unwrap_or(i64::MAX))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        messages.reverse();
+        Ok(messages.into_iter().map(Into::into).collect())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RoomSummary {
+    room_id: u32,
+    users: usize,
+}
+
+/// A page of a room's history, and where the next older page starts if there is one
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct HistoryPage {
+    messages: Vec<RoomMessage>,
+    before: Option<i64>,
 }
 
 #[post("/reset")]
 fn reset(chat_state: &rocket::State<ChatState>) -> Result<(), Error> {
-    let mut state = chat_state.write()?;
-    state.views = 0;
+    chat_state.write()?.views = 0;
     Ok(())
 }
 
@@ -142,104 +362,420 @@ fn views(chat_state: &rocket::State<ChatState>) -> Result<String, Error> {
     Ok(views.to_string())
 }
 
+/// A room's messages, newest page first: pass a page's `before` to get the one before it
+#[get("/room/<room_id>/history?<before>&<limit>")]
+async fn history(
+    room_id: u32,
+    before: Option<i64>,
+    limit: Option<u32>,
+    chat_state: &rocket::State<ChatState>,
+    config: &rocket::State<ChatConfig>,
+) -> Result<Json<HistoryPage>, ApiError> {
+    let limit = limit.unwrap_or(config.max_page_size);
+    if !(1..=config.max_page_size).contains(&limit) {
+        return Err(InputError {
+            message: "Page size is out of range",
+        }
+        .into());
+    }
+    // One extra message tells whether there's an older page
+    let mut messages = chat_state.history(room_id, before, limit + 1).await?;
+    let more = messages.len() > limit as usize;
+    if more {
+        messages.remove(0);
+    }
+    let before = messages.first().map(|m| m.id).filter(|_| more);
+    Ok(Json(HistoryPage { messages, before }))
+}
+
+#[get("/rooms")]
+fn rooms(chat_state: &rocket::State<ChatState>) -> Result<Json<Vec<RoomSummary>>, Error> {
+    Ok(Json(chat_state.rooms()?))
+}
+
+#[get("/room/<room_id>/users")]
+fn room_users(
+    room_id: u32,
+    chat_state: &rocket::State<ChatState>,
+) -> Result<Json<Vec<String>>, Error> {
+    Ok(Json(chat_state.users(room_id)?))
+}
+
 #[get("/ws/room/<room_id>/user/<user_id>")]
 fn ws_room<'r>(
     chat_state: &'r rocket::State<ChatState>,
+    config: &'r rocket::State<ChatConfig>,
     ws: ws::WebSocket,
     room_id: u32,
     user_id: &'r str,
 ) -> ws::Channel<'r> {
     ws.channel(move |stream| {
-        let (mut sink, mut stream) = stream.split();
-        Box::pin(async move {
-            // Connect user to room
-            let mut user = {
-                let mut state = chat_state
-                    .write()
-                    .expect("couldn't get write lock on state to add user");
-                let (user, tx) = User::new(user_id.to_string());
-                state
-                    .users
-                    .push(RoomUser::new(user.id.clone(), room_id, tx.clone()));
-                eprintln!("added user {} to room {room_id}", &user.id);
-                user
-            };
-
-            let res = future::join(
-                // Sending a message to the room
-                Box::pin(async {
-                    while let Some(msg) = stream.next().await {
-                        match msg? {
-                            Message::Text(text) => {
-                                if let Ok(user_msg) = UserMessage::try_from(text.as_str()) {
-                                    if let Some(room_msg) =
-                                        RoomMessage::new(room_id, user.id.clone(), user_msg.message)
-                                    {
-                                        let txs: Result<Vec<_>, Error> =
-                                            chat_state.read().map(|state| {
-                                                state
-                                                    .users
-                                                    .iter()
-                                                    .filter(|u| u.room_id == room_id)
-                                                    .map(|u| u.tx.clone())
-                                                    .collect()
-                                            });
-
-                                        if let Ok(txs) = txs {
-                                            for mut tx in txs {
-                                                let _ = tx.send(room_msg.clone()).await;
-                                            }
-                                        }
-                                    }
+        let (sink, stream) = stream.split();
+        Box::pin(chat(chat_state, config, room_id, user_id, sink, stream))
+    })
+}
+
+/// Runs one connection to a room, from joining to leaving
+async fn chat<S, R>(
+    chat_state: &ChatState,
+    config: &ChatConfig,
+    room_id: u32,
+    user_id: &str,
+    mut sink: S,
+    mut stream: R,
+) -> ws::result::Result<()>
+where
+    S: Sink<Message> + Unpin,
+    R: Stream<Item = ws::result::Result<Message>> + Unpin,
+{
+    // Connect user to room
+    let (mut user, arrived) = chat_state
+        .join(room_id, user_id)
+        .expect("couldn't get write lock on state to add user");
+    eprintln!("added user {} to room {room_id}", &user.id);
+    if arrived {
+        let join = RoomEvent::Join(Presence::new(user.id.clone()));
+        let _ = chat_state.broadcast(room_id, join).await;
+    }
+
+    // Catch the user up on the room, which doesn't count towards views. Anything sent
+    // since they joined is both here and on its way to them, so skip it when it arrives
+    let mut replayed = 0;
+    match chat_state.history(room_id, None, config.replay).await {
+        Ok(messages) => {
+            for room_msg in messages {
+                replayed = room_msg.id;
+                // A closed socket is noticed and cleaned up below
+                if sink
+                    .send(RoomEvent::Message(room_msg).into())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+        Err(e) => eprintln!("couldn't replay room {room_id}: {}", e.message),
+    }
+
+    let res = future::join(
+        // Sending a message to the room
+        Box::pin(async {
+            let received = async {
+                while let Some(msg) = stream.next().await {
+                    match msg? {
+                        Message::Text(text) => {
+                            if let Ok(user_msg) = UserMessage::try_from(text.as_str()) {
+                                let saved = chat_state
+                                    .save(room_id, user.id.clone(), user_msg.message)
+                                    .await;
+                                if let Err(e) = &saved {
+                                    eprintln!("couldn't save message: {}", e.message);
+                                }
+                                if let Ok(Some(room_msg)) = saved {
+                                    let _ = chat_state
+                                        .broadcast(room_id, RoomEvent::Message(room_msg))
+                                        .await;
                                 }
                             }
-                            Message::Close(c) => {
-                                println!("User {} channel closed with {c:?}", &user.id);
-                                break;
-                            }
-                            _ => {}
                         }
+                        Message::Close(c) => {
+                            println!("User {} channel closed with {c:?}", &user.id);
+                            break;
+                        }
+                        _ => {}
                     }
+                }
+                Ok(())
+            }
+            .await;
 
-                    // Log user out of room when socket closes
-                    if let Ok(mut state) = chat_state.write() {
-                        eprintln!("logging out {}", &user.id);
-                        state.users.retain(|u| u.id != user.id);
-                    }
-
-                    Ok(())
-                }),
-                // Receiving a message from the room
-                Box::pin(async {
-                    while let Some(room_msg) = user.rx.next().await {
-                        if let Ok(mut state) = chat_state.write() {
-                            // Check that the user is still in the room
-                            if !state
-                                .users
-                                .iter()
-                                .any(|u| u.room_id == room_msg.room_id && u.id == user.id)
-                            {
-                                continue;
-                            }
+            // Log user out of room when socket closes, cleanly or not. This also drops their
+            // sender, which ends the receiving half
+            eprintln!("logging out {}", &user.id);
+            if let Ok(true) = chat_state.leave(room_id, &user.id, user.connection) {
+                let leave = RoomEvent::Leave(Presence::new(user.id.clone()));
+                let _ = chat_state.broadcast(room_id, leave).await;
+            }
 
-                            state.views += 1;
+            received
+        }),
+        // Receiving a message from the room
+        Box::pin(async {
+            while let Some(event) = user.rx.next().await {
+                // Only chat messages count as views
+                if let RoomEvent::Message(room_msg) = &event {
+                    if room_msg.id <= replayed {
+                        continue;
+                    }
+                    if let Ok(mut state) = chat_state.write() {
+                        // Check that the user is still in the room
+                        if !state.users.iter().any(|u| {
+                            u.room_id == room_msg.room_id && u.connection == user.connection
+                        }) {
+                            continue;
                         }
 
-                        let _ = sink.send(room_msg.into()).await;
+                        state.views += 1;
                     }
-                    Ok(())
-                }),
-            )
-            .await;
+                }
 
-            match res {
-                (Err(e), _) | (_, Err(e)) => Err(e),
-                _ => Ok(()),
+                let _ = sink.send(event.into()).await;
             }
-        })
-    })
+            Ok(())
+        }),
+    )
+    .await;
+
+    match res {
+        (Err(e), _) | (_, Err(e)) => Err(e),
+        _ => Ok(()),
+    }
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![ws_ping, reset, views, ws_room]
+    routes![ws_ping, reset, views, history, rooms, room_users, ws_room]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::Value;
+    use sqlx::SqlitePool;
+
+    use rocket::futures::channel::mpsc;
+    use rocket::futures::prelude::*;
+    use rocket::serde::json::serde_json;
+    use rocket_ws as ws;
+    use ws::Message;
+
+    use super::{ChatConfig, ChatState, Presence, RoomEvent};
+
+    async fn client() -> Client {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let chat_state = ChatState::new(pool).await.unwrap();
+        for (room_id, user, message) in [(1, "elf", "first"), (2, "santa", "elsewhere")] {
+            chat_state
+                .save(room_id, user.into(), message.into())
+                .await
+                .unwrap();
+        }
+        for i in 2..=5 {
+            chat_state
+                .save(1, "elf".into(), format!("message {i}"))
+                .await
+                .unwrap();
+        }
+        Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .manage(chat_state)
+                .manage(ChatConfig::default()),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn page(client: &Client, uri: String) -> (Vec<String>, Value) {
+        let body: Value = client.get(uri).dispatch().await.into_json().await.unwrap();
+        let messages = body["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["message"].as_str().unwrap().to_string())
+            .collect();
+        (messages, body["before"].clone())
+    }
+
+    #[rocket::async_test]
+    async fn history_test() {
+        let client = client().await;
+
+        let (messages, before) = page(&client, "/room/1/history?limit=2".into()).await;
+        assert_eq!(vec!["message 4", "message 5"], messages);
+        let (messages, before) =
+            page(&client, format!("/room/1/history?limit=2&before={before}")).await;
+        assert_eq!(vec!["message 2", "message 3"], messages);
+        let (messages, before) =
+            page(&client, format!("/room/1/history?limit=2&before={before}")).await;
+        assert_eq!(vec!["first"], messages);
+        assert_eq!(Value::Null, before);
+
+        let body: Value = client
+            .get("/room/2/history")
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!("santa", body["messages"][0]["user"]);
+        assert!(body["messages"][0]["timestamp"]
+            .as_str()
+            .unwrap()
+            .ends_with('Z'));
+        assert_eq!(1, body["messages"].as_array().unwrap().len());
+
+        let response = client.get("/room/1/history?limit=0").dispatch().await;
+        assert_eq!(Status::BadRequest, response.status());
+
+        // Resetting only resets the views, the history is kept
+        let response = client.post("/reset").dispatch().await;
+        assert_eq!(Status::Ok, response.status());
+        let (messages, _) = page(&client, "/room/1/history".into()).await;
+        assert_eq!(5, messages.len());
+    }
+
+    async fn get(client: &Client, uri: &'static str) -> Value {
+        let response = client.get(uri).dispatch().await;
+        response.into_json().await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn presence_test() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let chat_state = ChatState::new(pool).await.unwrap();
+
+        let (mut elf, arrived) = chat_state.join(1, "elf").unwrap();
+        assert!(arrived);
+        let (_, arrived) = chat_state.join(1, "santa").unwrap();
+        assert!(arrived);
+        // A second connection from the same user isn't news to the room
+        let (santa_again, arrived) = chat_state.join(1, "santa").unwrap();
+        assert!(!arrived);
+        chat_state.join(2, "rudolph").unwrap();
+
+        let leave = |user: &str| RoomEvent::Leave(Presence::new(user.into()));
+        chat_state.broadcast(1, leave("santa")).await.unwrap();
+        let event = serde_json::to_value(elf.rx.next().await.unwrap()).unwrap();
+        assert_eq!("leave", event["type"]);
+        assert_eq!("santa", event["user"]);
+
+        let message = chat_state
+            .save(1, "elf".into(), "hi".into())
+            .await
+            .unwrap()
+            .unwrap();
+        chat_state
+            .broadcast(1, RoomEvent::Message(message))
+            .await
+            .unwrap();
+        let event = serde_json::to_value(elf.rx.next().await.unwrap()).unwrap();
+        assert_eq!("message", event["type"]);
+        assert_eq!("hi", event["message"]);
+
+        let client = Client::tracked(
+            rocket::build()
+                .mount("/", super::routes())
+                .manage(chat_state)
+                .manage(ChatConfig::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::json!([{"room_id": 1, "users": 2}, {"room_id": 2, "users": 1}]),
+            get(&client, "/rooms").await
+        );
+        assert_eq!(
+            serde_json::json!(["elf", "santa"]),
+            get(&client, "/room/1/users").await
+        );
+        assert_eq!(serde_json::json!([]), get(&client, "/room/3/users").await);
+
+        let chat_state = client.rocket().state::<ChatState>().unwrap();
+        assert!(!chat_state
+            .leave(1, "santa", santa_again.connection)
+            .unwrap());
+        assert!(chat_state.leave(1, "elf", elf.connection).unwrap());
+        assert_eq!(
+            serde_json::json!(["santa"]),
+            get(&client, "/room/1/users").await
+        );
+    }
+
+    /// Joins room 1 as `user`, returning frames to send and frames received
+    fn connect<'a>(
+        chat_state: &'a ChatState,
+        config: &'a ChatConfig,
+        user: &'static str,
+    ) -> (
+        mpsc::UnboundedSender<ws::result::Result<Message>>,
+        mpsc::UnboundedReceiver<Message>,
+        impl Future<Output = ws::result::Result<()>> + 'a,
+    ) {
+        let (incoming, stream) = mpsc::unbounded();
+        let (sink, sent) = mpsc::unbounded();
+        let session = super::chat(chat_state, config, 1, user, sink, stream);
+        (incoming, sent, session)
+    }
+
+    async fn next_event(sent: &mut mpsc::UnboundedReceiver<Message>) -> Value {
+        let frame = sent.next().await.unwrap();
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn replay_test() {
+        let client = client().await;
+        let chat_state = client.rocket().state::<ChatState>().unwrap();
+        let config = ChatConfig {
+            replay: 2,
+            ..ChatConfig::default()
+        };
+        let (incoming, mut sent, session) = connect(chat_state, &config, "santa");
+
+        let user = async {
+            // The latest messages come first, then the room hears about the user joining
+            let replayed = [next_event(&mut sent).await, next_event(&mut sent).await];
+            assert_eq!(
+                ["message 4", "message 5"],
+                replayed.map(|event| event["message"].as_str().unwrap().to_string())
+            );
+            assert_eq!("join", next_event(&mut sent).await["type"]);
+
+            // A message that was replayed isn't sent again when its broadcast arrives
+            let old = chat_state.history(1, None, 1).await.unwrap().remove(0);
+            chat_state
+                .broadcast(1, RoomEvent::Message(old))
+                .await
+                .unwrap();
+            incoming
+                .unbounded_send(Ok(Message::text(r#"{"message":"ho ho ho"}"#)))
+                .unwrap();
+            let event = next_event(&mut sent).await;
+            assert_eq!("ho ho ho", event["message"]);
+            assert_eq!("santa", event["user"]);
+            // Only messages that weren't replayed count as views
+            assert_eq!(1, chat_state.read().unwrap().views);
+
+            incoming.unbounded_send(Ok(Message::Close(None))).unwrap();
+        };
+        let (result, ()) = future::join(session, user).await;
+        result.unwrap();
+        assert!(chat_state.users(1).unwrap().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn disconnect_test() {
+        let client = client().await;
+        let chat_state = client.rocket().state::<ChatState>().unwrap();
+        let config = ChatConfig::default();
+        let (mut elf, _) = chat_state.join(1, "elf").unwrap();
+        let (incoming, _sent, session) = connect(chat_state, &config, "santa");
+
+        // The connection drops without a closing handshake
+        incoming
+            .unbounded_send(Err(ws::result::Error::Io(
+                std::io::ErrorKind::ConnectionReset.into(),
+            )))
+            .unwrap();
+        assert!(session.await.is_err());
+
+        let event = serde_json::to_value(elf.rx.next().await.unwrap()).unwrap();
+        assert_eq!("join", event["type"]);
+        let event = serde_json::to_value(elf.rx.next().await.unwrap()).unwrap();
+        assert_eq!("leave", event["type"]);
+        assert_eq!("santa", event["user"]);
+        assert_eq!(vec!["elf"], chat_state.users(1).unwrap());
+    }
 }
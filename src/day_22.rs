@@ -1,11 +1,10 @@
 use std::collections::VecDeque;
-use std::num::ParseIntError;
 use std::str::FromStr;
 
 use rocket::data::{Data, ToByteUnit};
-use rocket::{post, routes, Responder, Route};
+use rocket::{post, routes, Route};
 
-use crate::common::Error;
+use crate::common::{Error, InputError};
 
 #[post("/integers", data = "<int_strs>")]
 async fn integers(int_strs: Data<'_>) -> Result<String, Error> {
@@ -24,26 +23,6 @@ async fn integers(int_strs: Data<'_>) -> Result<String, Error> {
     Ok("🎁".repeat(uniq_int))
 }
 
-#[derive(Responder, Debug)]
-#[response(status = 400)]
-pub struct InputError {
-    pub message: &'static str,
-}
-
-impl From<Error> for InputError {
-    fn from(error: Error) -> Self {
-        Self {
-            message: error.message,
-        }
-    }
-}
-
-impl From<ParseIntError> for InputError {
-    fn from(error: ParseIntError) -> Self {
-        Error::from(error).into()
-    }
-}
-
 #[derive(Clone, Copy)]
 struct StarCoords {
     x: i32,
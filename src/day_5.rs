@@ -15,7 +15,7 @@ fn name_slice(
     offset: Option<usize>,
     limit: Option<usize>,
     split: Option<usize>,
-) -> Json<Names> {
+) -> Json<Names<'_>> {
     let off = offset.unwrap_or(0);
     let lim = limit.unwrap_or(names.len());
 
@@ -1,14 +1,20 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
 use base64::engine::general_purpose;
 use base64::Engine;
-use rocket::http::Status;
+use rocket::http::{Cookie, CookieJar, Status};
 use rocket::request::{FromRequest, Outcome};
 use rocket::serde::json::{serde_json, Json};
-use rocket::serde::{Deserialize, Serialize};
-use rocket::{get, routes, Request, Route};
+use rocket::serde::ser::SerializeStruct;
+use rocket::serde::{Deserialize, Serialize, Serializer};
+use rocket::tokio::task::spawn_blocking;
+use rocket::{get, post, routes, FromFormField, Request, Route, State};
+use ulid::Ulid;
 
-use crate::common::Error;
+use crate::common::{ApiError, Error, InputError};
 
 struct CookieHeader {
     value: String,
@@ -51,7 +57,195 @@ fn cookie_recipe(cookie_header: CookieHeader) -> String {
     cookie_header.value
 }
 
-type Ingredients = HashMap<String, u64>;
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum Unit {
+    G,
+    Kg,
+    Ml,
+    L,
+    #[serde(alias = "cup")]
+    Cups,
+    #[serde(alias = "piece", alias = "pcs")]
+    Pieces,
+}
+
+#[derive(PartialEq, Eq)]
+enum Dimension {
+    Mass,
+    Volume,
+    Count,
+}
+
+impl Unit {
+    fn dimension(self) -> Dimension {
+        match self {
+            Unit::G | Unit::Kg => Dimension::Mass,
+            Unit::Ml | Unit::L | Unit::Cups => Dimension::Volume,
+            Unit::Pieces => Dimension::Count,
+        }
+    }
+
+    /// Size of the unit in grams, millilitres or pieces
+    fn base_factor(self) -> f64 {
+        match self {
+            Unit::G | Unit::Ml | Unit::Pieces => 1.0,
+            Unit::Kg | Unit::L => 1000.0,
+            // US customary cup
+            Unit::Cups => 236.588_236_5,
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_lowercase())).map_err(|_| {
+            InputError {
+                message: "Unknown unit, expected one of g, kg, ml, l, cups or pieces",
+            }
+        })
+    }
+}
+
+/// Leeway for floating point error when comparing amounts
+const EPSILON: f64 = 1e-9;
+
+/// An amount of an ingredient. Amounts without a unit only combine with other amounts
+/// without a unit, which keeps the original unitless API working as it did.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(crate = "rocket::serde", try_from = "QuantityRepr")]
+struct Quantity {
+    amount: f64,
+    unit: Option<Unit>,
+}
+
+/// The ways a quantity can be written: `250`, `"250 g"` or `{"amount": 250, "unit": "g"}`
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum QuantityRepr {
+    Bare(f64),
+    Text(String),
+    Full { amount: f64, unit: Option<Unit> },
+}
+
+impl TryFrom<QuantityRepr> for Quantity {
+    type Error = InputError;
+
+    fn try_from(repr: QuantityRepr) -> Result<Self, Self::Error> {
+        let (amount, unit) = match repr {
+            QuantityRepr::Bare(amount) => (amount, None),
+            QuantityRepr::Full { amount, unit } => (amount, unit),
+            QuantityRepr::Text(text) => {
+                let text = text.trim();
+                let split = text
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or(text.len());
+                let (amount, unit) = text.split_at(split);
+                let amount = amount.parse().map_err(|_| InputError {
+                    message: "Quantity must start with a number",
+                })?;
+                let unit = unit.trim();
+                let unit = if unit.is_empty() {
+                    None
+                } else {
+                    Some(unit.parse()?)
+                };
+                (amount, unit)
+            }
+        };
+
+        if !amount.is_finite() || amount < 0.0 {
+            return Err(InputError {
+                message: "Quantities must be non-negative numbers",
+            });
+        }
+
+        Ok(Self { amount, unit })
+    }
+}
+
+impl Quantity {
+    fn new(amount: f64, unit: Option<Unit>) -> Self {
+        Self { amount, unit }
+    }
+
+    /// The amount expressed in another unit
+    fn amount_in(self, unit: Option<Unit>) -> Result<f64, InputError> {
+        let err = |message| Err(InputError { message });
+        match (self.unit, unit) {
+            (None, None) => Ok(self.amount),
+            (Some(from), Some(to)) if from.dimension() == to.dimension() => {
+                Ok(self.amount * from.base_factor() / to.base_factor())
+            }
+            (Some(from), Some(to)) => match (from.dimension(), to.dimension()) {
+                (Dimension::Mass, Dimension::Volume) | (Dimension::Volume, Dimension::Mass) => {
+                    err("Incompatible units: can't convert between mass and volume")
+                }
+                (Dimension::Count, _) | (_, Dimension::Count) => {
+                    err("Incompatible units: can't convert pieces to mass or volume")
+                }
+                _ => unreachable!("units of the same dimension are convertible"),
+            },
+            _ => err("Incompatible units: can't mix quantities with and without a unit"),
+        }
+    }
+
+    fn convert(self, unit: Option<Unit>) -> Result<Self, InputError> {
+        Ok(Self::new(self.amount_in(unit)?, unit))
+    }
+}
+
+/// Serializes whole amounts as integers, so unitless ingredients look the way they always have
+struct Amount(f64);
+
+impl Serialize for Amount {
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Round away floating point error from unit conversions
+        let amount = (self.0 * 1e6).round() / 1e6;
+        if amount.fract() == 0.0 && amount < u64::MAX as f64 {
+            serializer.serialize_u64(amount as u64)
+        } else {
+            serializer.serialize_f64(amount)
+        }
+    }
+}
+
+impl Serialize for Quantity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.unit {
+            None => Amount(self.amount).serialize(serializer),
+            Some(unit) => {
+                let mut quantity = serializer.serialize_struct("Quantity", 2)?;
+                quantity.serialize_field("amount", &Amount(self.amount))?;
+                quantity.serialize_field("unit", &unit)?;
+                quantity.end()
+            }
+        }
+    }
+}
+
+type Ingredients = HashMap<String, Quantity>;
+
+/// The recipe with each ingredient the pantry stocks converted into the pantry's unit
+fn in_pantry_units(recipe: &Ingredients, pantry: &Ingredients) -> Result<Ingredients, InputError> {
+    recipe
+        .iter()
+        .map(|(ing, r_qty)| {
+            let r_qty = match pantry.get(ing) {
+                Some(p_qty) => r_qty.convert(p_qty.unit)?,
+                None => *r_qty,
+            };
+            Ok((ing.clone(), r_qty))
+        })
+        .collect()
+}
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -60,30 +254,80 @@ struct Recipe {
     pantry: Ingredients,
 }
 
-impl Recipe {
-    fn bake(mut self) -> AfterBake {
-        let cookies = self
-            .recipe
-            .iter()
-            .map(|(ing, r_amt)| {
-                self.pantry
-                    .get(ing)
-                    .map_or(0, |p_amt| if *r_amt == 0 { 0 } else { p_amt / r_amt })
+/// How many batches of the recipe the pantry allows, or `None` if the recipe doesn't use
+/// up any ingredient. The recipe has to be in the pantry's units.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn max_batches(recipe: &Ingredients, pantry: &Ingredients) -> Option<u64> {
+    // Ingredients with a zero amount don't constrain anything, but an ingredient the
+    // pantry doesn't have means no cookies at all
+    recipe
+        .iter()
+        .filter(|(_, r_qty)| r_qty.amount > 0.0)
+        .map(|(ing, r_qty)| {
+            pantry.get(ing).map_or(0, |p_qty| {
+                (p_qty.amount / r_qty.amount + EPSILON).floor() as u64
             })
-            .filter(|amt| *amt > 0)
-            .min()
-            .unwrap_or(0);
+        })
+        .min()
+}
 
-        for (ing, p_amt) in &mut self.pantry {
-            if let Some(r_amt) = self.recipe.get(ing) {
-                *p_amt -= r_amt * cookies;
+/// Takes the ingredients for the given number of batches out of the pantry. The recipe has to
+/// be in the pantry's units.
+#[allow(clippy::cast_precision_loss)]
+fn consume(pantry: &mut Ingredients, recipe: &Ingredients, batches: u64) {
+    for (ing, p_qty) in pantry {
+        if let Some(r_qty) = recipe.get(ing) {
+            p_qty.amount -= r_qty.amount * batches as f64;
+            if p_qty.amount < EPSILON {
+                p_qty.amount = 0.0;
             }
         }
+    }
+}
 
-        AfterBake {
+impl Recipe {
+    /// Ingredients the recipe calls for that the pantry doesn't stock at all
+    fn missing(&self) -> Vec<String> {
+        let mut missing: Vec<_> = self
+            .recipe
+            .iter()
+            .filter(|(ing, r_qty)| r_qty.amount > 0.0 && !self.pantry.contains_key(*ing))
+            .map(|(ing, _)| ing.clone())
+            .collect();
+        missing.sort();
+        missing
+    }
+
+    fn bake(mut self) -> Result<AfterBake, InputError> {
+        let recipe = in_pantry_units(&self.recipe, &self.pantry)?;
+        let cookies = max_batches(&recipe, &self.pantry).unwrap_or(0);
+        let missing = self.missing();
+        consume(&mut self.pantry, &recipe, cookies);
+
+        Ok(AfterBake {
             cookies,
             pantry: self.pantry,
+            missing,
+        })
+    }
+
+    /// How much of each ingredient has to be bought to bake the given number of cookies, in
+    /// the recipe's units
+    #[allow(clippy::cast_precision_loss)]
+    fn shopping_list(&self, cookies: u64) -> Result<Ingredients, InputError> {
+        let mut shopping = Ingredients::new();
+        for (ing, r_qty) in &self.recipe {
+            let needed = r_qty.amount * cookies as f64;
+            let stocked = match self.pantry.get(ing) {
+                Some(p_qty) => p_qty.amount_in(r_qty.unit)?,
+                None => 0.0,
+            };
+            let short = needed - stocked;
+            if short > EPSILON {
+                shopping.insert(ing.clone(), Quantity::new(short, r_qty.unit));
+            }
         }
+        Ok(shopping)
     }
 }
 
@@ -92,10 +336,12 @@ impl Recipe {
 struct AfterBake {
     cookies: u64,
     pantry: Ingredients,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    missing: Vec<String>,
 }
 
 #[get("/7/bake")]
-fn bake_cookies(header: CookieHeader) -> Result<Json<AfterBake>, Error> {
+fn bake_cookies(header: CookieHeader) -> Result<Json<AfterBake>, ApiError> {
     let recipe: Recipe = serde_json::from_str(&header.value).map_err(|e| {
         if cfg!(debug_assertions) {
             dbg!(e);
@@ -105,22 +351,425 @@ fn bake_cookies(header: CookieHeader) -> Result<Json<AfterBake>, Error> {
         }
     })?;
 
-    Ok(Json(recipe.bake()))
+    Ok(Json(recipe.bake()?))
+}
+
+/// How long a pantry is kept after it was last restocked or baked from
+const PANTRY_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+/// Upper limit on the number of pantries kept at once; the least recently used one makes room
+const MAX_PANTRIES: usize = 10_000;
+
+struct StoredPantry {
+    ingredients: Ingredients,
+    touched: Instant,
+}
+
+/// Pantries that outlive a single bake, keyed by session id
+pub struct Pantries {
+    store: RwLock<HashMap<String, StoredPantry>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl Pantries {
+    pub fn new() -> Self {
+        Self::with_limits(PANTRY_TTL, MAX_PANTRIES)
+    }
+
+    fn with_limits(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            store: RwLock::new(HashMap::new()),
+            ttl,
+            capacity,
+        }
+    }
+
+    fn get(&self, session: &PantrySession) -> Result<Ingredients, Error> {
+        Ok(self
+            .store
+            .read()?
+            .get(&session.id)
+            .filter(|stored| stored.touched.elapsed() < self.ttl)
+            .map(|stored| stored.ingredients.clone())
+            .unwrap_or_default())
+    }
+
+    /// The session's pantry, making room for it first if it's new
+    fn pantry_mut<'s>(
+        &self,
+        store: &'s mut HashMap<String, StoredPantry>,
+        session: &PantrySession,
+    ) -> &'s mut Ingredients {
+        if !store.contains_key(&session.id) {
+            store.retain(|_, stored| stored.touched.elapsed() < self.ttl);
+            while store.len() >= self.capacity {
+                let Some(oldest) = store
+                    .iter()
+                    .min_by_key(|(_, stored)| stored.touched)
+                    .map(|(id, _)| id.clone())
+                else {
+                    break;
+                };
+                store.remove(&oldest);
+            }
+        }
+
+        let stored = store
+            .entry(session.id.clone())
+            .or_insert_with(|| StoredPantry {
+                ingredients: Ingredients::default(),
+                touched: Instant::now(),
+            });
+        if stored.touched.elapsed() >= self.ttl {
+            stored.ingredients.clear();
+        }
+        stored.touched = Instant::now();
+        &mut stored.ingredients
+    }
+
+    fn restock(
+        &self,
+        session: &PantrySession,
+        delivery: Ingredients,
+    ) -> Result<Ingredients, ApiError> {
+        let mut store = self.store.write().map_err(Error::from)?;
+        let pantry = self.pantry_mut(&mut store, session);
+        // Check every unit before stocking anything, so a bad delivery is turned away whole
+        let delivery = in_pantry_units(&delivery, pantry)?;
+        for (ing, d_qty) in delivery {
+            pantry
+                .entry(ing)
+                .and_modify(|p_qty| p_qty.amount += d_qty.amount)
+                .or_insert(d_qty);
+        }
+        Ok(pantry.clone())
+    }
+
+    fn bake(&self, session: &PantrySession, recipe: Ingredients) -> Result<AfterBake, ApiError> {
+        let mut store = self.store.write().map_err(Error::from)?;
+        let pantry = self.pantry_mut(&mut store, session);
+        let after_bake = Recipe {
+            recipe,
+            pantry: pantry.clone(),
+        }
+        .bake()?;
+        pantry.clone_from(&after_bake.pantry);
+        Ok(after_bake)
+    }
+}
+
+const SESSION_COOKIE: &str = "pantry_session";
+
+/// Identifies the caller's pantry, starting a new session if the request doesn't carry one
+struct PantrySession {
+    id: String,
+}
+
+impl PantrySession {
+    /// The session the request already carries, if any
+    fn existing(cookies: &CookieJar<'_>) -> Option<Self> {
+        cookies.get(SESSION_COOKIE).map(|cookie| Self {
+            id: cookie.value().to_string(),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PantrySession {
+    type Error = Error;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cookies = req.cookies();
+        let session = Self::existing(cookies).unwrap_or_else(|| {
+            let id = Ulid::new().to_string();
+            cookies.add(Cookie::build((SESSION_COOKIE, id.clone())).path("/7"));
+            PantrySession { id }
+        });
+
+        Outcome::Success(session)
+    }
+}
+
+#[get("/7/pantry")]
+fn inspect_pantry(
+    pantries: &State<Pantries>,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<Ingredients>, Error> {
+    // Only writes start a session, there's nothing to look at until then
+    Ok(Json(match PantrySession::existing(cookies) {
+        Some(session) => pantries.get(&session)?,
+        None => Ingredients::default(),
+    }))
+}
+
+#[post("/7/pantry", data = "<delivery>")]
+fn restock_pantry(
+    pantries: &State<Pantries>,
+    session: PantrySession,
+    delivery: Json<Ingredients>,
+) -> Result<Json<Ingredients>, ApiError> {
+    Ok(Json(pantries.restock(&session, delivery.into_inner())?))
+}
+
+#[post("/7/pantry/bake", data = "<recipe>")]
+fn bake_from_pantry(
+    pantries: &State<Pantries>,
+    session: PantrySession,
+    recipe: Json<Ingredients>,
+) -> Result<Json<AfterBake>, ApiError> {
+    Ok(Json(pantries.bake(&session, recipe.into_inner())?))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ShoppingList {
+    cookies: u64,
+    shopping: Ingredients,
+    missing: Vec<String>,
+}
+
+#[post("/7/shopping?<cookies>", data = "<recipe>")]
+fn shopping_list(recipe: Json<Recipe>, cookies: u64) -> Result<Json<ShoppingList>, InputError> {
+    Ok(Json(ShoppingList {
+        cookies,
+        shopping: recipe.shopping_list(cookies)?,
+        missing: recipe.missing(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ValuedRecipe {
+    name: String,
+    recipe: Ingredients,
+    #[serde(default = "ValuedRecipe::default_value")]
+    value: f64,
+}
+
+impl ValuedRecipe {
+    fn default_value() -> f64 {
+        1.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Bakery {
+    recipes: Vec<ValuedRecipe>,
+    pantry: Ingredients,
+}
+
+#[derive(FromFormField, Default, Clone, Copy)]
+enum Objective {
+    #[default]
+    Value,
+    Cookies,
+}
+
+/// Upper limit on the number of branches explored before settling for the best plan so far
+const PLAN_SEARCH_BUDGET: usize = 1_000_000;
+/// Most recipes a plan can choose between. The search recurses once per recipe, so this also
+/// bounds its depth
+const MAX_PLAN_RECIPES: usize = 64;
+
+/// Branch and bound search over the batch counts of each recipe
+struct Planner<'b> {
+    recipes: &'b [ValuedRecipe],
+    objective: Objective,
+    best: Option<(f64, Vec<u64>)>,
+    explored: usize,
+}
+
+impl<'b> Planner<'b> {
+    fn new(recipes: &'b [ValuedRecipe], objective: Objective) -> Self {
+        Self {
+            recipes,
+            objective,
+            best: None,
+            explored: 0,
+        }
+    }
+
+    fn weight(&self, i: usize) -> f64 {
+        match self.objective {
+            Objective::Value => self.recipes[i].value,
+            Objective::Cookies => 1.0,
+        }
+    }
+
+    fn best_value(&self) -> Option<f64> {
+        self.best.as_ref().map(|(value, _)| *value)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn search(&mut self, i: usize, pantry: &Ingredients, counts: &mut Vec<u64>, value: f64) {
+        self.explored += 1;
+
+        if i == self.recipes.len() {
+            if self.best_value().is_none_or(|best| value > best) {
+                self.best = Some((value, counts.clone()));
+            }
+            return;
+        }
+
+        // Baking every remaining recipe as if it had the pantry to itself can't be beaten
+        let bound = value
+            + (i..self.recipes.len())
+                .map(|j| {
+                    self.weight(j)
+                        * max_batches(&self.recipes[j].recipe, pantry).unwrap_or(0) as f64
+                })
+                .sum::<f64>();
+        if self.best_value().is_some_and(|best| bound <= best) {
+            return;
+        }
+
+        let max = max_batches(&self.recipes[i].recipe, pantry).unwrap_or(0);
+        // With no other recipe left to share with, the last one should bake as much as it can
+        let min = if i + 1 == self.recipes.len() { max } else { 0 };
+
+        for batches in (min..=max).rev() {
+            if self.explored >= PLAN_SEARCH_BUDGET {
+                return;
+            }
+
+            let mut pantry = pantry.clone();
+            consume(&mut pantry, &self.recipes[i].recipe, batches);
+            counts.push(batches);
+            self.search(
+                i + 1,
+                &pantry,
+                counts,
+                value + self.weight(i) * batches as f64,
+            );
+            counts.pop();
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct BakingPlan {
+    plan: HashMap<String, u64>,
+    cookies: u64,
+    value: f64,
+    pantry: Ingredients,
+    optimal: bool,
+}
+
+impl Bakery {
+    fn plan(mut self, objective: Objective) -> Result<BakingPlan, InputError> {
+        if self.recipes.len() > MAX_PLAN_RECIPES {
+            return Err(InputError {
+                message: "A plan can choose between at most 64 recipes",
+            });
+        }
+        for recipe in &mut self.recipes {
+            recipe.recipe = in_pantry_units(&recipe.recipe, &self.pantry)?;
+        }
+
+        for (i, recipe) in self.recipes.iter().enumerate() {
+            if !recipe.value.is_finite() || recipe.value < 0.0 {
+                return Err(InputError {
+                    message: "Recipe values must be non-negative numbers",
+                });
+            }
+            if max_batches(&recipe.recipe, &self.pantry).is_none() {
+                return Err(InputError {
+                    message: "Every recipe has to use some ingredient",
+                });
+            }
+            if self.recipes[..i].iter().any(|r| r.name == recipe.name) {
+                return Err(InputError {
+                    message: "Recipe names must be unique",
+                });
+            }
+        }
+
+        let mut planner = Planner::new(&self.recipes, objective);
+        planner.search(0, &self.pantry, &mut Vec::new(), 0.0);
+        let optimal = planner.explored < PLAN_SEARCH_BUDGET;
+        let counts = planner
+            .best
+            .map(|(_, counts)| counts)
+            .unwrap_or_else(|| vec![0; self.recipes.len()]);
+
+        let mut plan = HashMap::new();
+        let mut value = 0.0;
+        for (recipe, batches) in self.recipes.into_iter().zip(counts) {
+            consume(&mut self.pantry, &recipe.recipe, batches);
+            #[allow(clippy::cast_precision_loss)]
+            {
+                value += recipe.value * batches as f64;
+            }
+            plan.insert(recipe.name, batches);
+        }
+
+        Ok(BakingPlan {
+            cookies: plan.values().sum(),
+            plan,
+            value,
+            pantry: self.pantry,
+            optimal,
+        })
+    }
+}
+
+#[post("/7/plan?<objective>", data = "<bakery>")]
+async fn plan_bakery(
+    bakery: Json<Bakery>,
+    objective: Option<Objective>,
+) -> Result<Json<BakingPlan>, ApiError> {
+    let bakery = bakery.into_inner();
+    // The search can take a while, so keep it off the async workers
+    let plan = spawn_blocking(move || bakery.plan(objective.unwrap_or_default()))
+        .await
+        .map_err(Error::from)??;
+    Ok(Json(plan))
+}
+
+/// Scales every ingredient by `factor`, keeping each in its own unit
+fn scale(recipe: Ingredients, factor: f64) -> Result<Ingredients, InputError> {
+    if !factor.is_finite() || factor < 0.0 {
+        return Err(InputError {
+            message: "Scaling factor must be a non-negative number",
+        });
+    }
+
+    Ok(recipe
+        .into_iter()
+        .map(|(ing, qty)| (ing, Quantity::new(qty.amount * factor, qty.unit)))
+        .collect())
+}
+
+#[post("/7/scale?<factor>", data = "<recipe>")]
+fn scale_recipe(recipe: Json<Ingredients>, factor: f64) -> Result<Json<Ingredients>, InputError> {
+    Ok(Json(scale(recipe.into_inner(), factor)?))
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![cookie_recipe, bake_cookies,]
+    routes![
+        cookie_recipe,
+        bake_cookies,
+        inspect_pantry,
+        restock_pantry,
+        bake_from_pantry,
+        shopping_list,
+        scale_recipe,
+        plan_bakery,
+    ]
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::common::test_client;
+    use super::Pantries;
+    use crate::common::test_client_stateful;
 
     #[test]
     fn cookie_recipe_test() {
         use rocket::http::Header;
 
-        let client = test_client(super::routes());
+        let client = test_client_stateful(super::routes(), Pantries::new());
         let response = client
             .get("/7/decode")
             .header(Header::new(
@@ -139,7 +788,7 @@ mod tests {
     fn bake_cookies_test() {
         use rocket::http::Header;
 
-        let client = test_client(super::routes());
+        let client = test_client_stateful(super::routes(), Pantries::new());
 
         for (expected, header) in [
         (vec![
@@ -169,4 +818,218 @@ mod tests {
         }
     }
     }
+
+    #[test]
+    fn pantry_session_test() {
+        let client = test_client_stateful(super::routes(), Pantries::new());
+
+        // Looking doesn't start a session
+        let response = client.get("/7/pantry").dispatch();
+        assert!(response.cookies().get("pantry_session").is_none());
+        assert_eq!("{}", response.into_string().unwrap());
+
+        let response = client
+            .post("/7/pantry")
+            .body(r#"{"flour":250,"sugar":100}"#)
+            .dispatch();
+        assert!(response.cookies().get("pantry_session").is_some());
+        assert!(response.into_string().unwrap().contains(r#""flour":250"#));
+
+        for (cookies, flour) in [(2, 50), (0, 50)] {
+            let response = client
+                .post("/7/pantry/bake")
+                .body(r#"{"flour":100}"#)
+                .dispatch();
+            let body = response.into_string().unwrap();
+            assert!(body.contains(&format!(r#""cookies":{cookies}"#)), "{body}");
+            assert!(body.contains(&format!(r#""flour":{flour}"#)), "{body}");
+        }
+
+        let response = client.post("/7/pantry").body(r#"{"flour":50}"#).dispatch();
+        assert!(response.into_string().unwrap().contains(r#""flour":100"#));
+
+        let response = client.get("/7/pantry").dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.contains(r#""flour":100"#), "{body}");
+        assert!(body.contains(r#""sugar":100"#), "{body}");
+    }
+
+    #[test]
+    fn pantry_eviction_test() {
+        use super::{Ingredients, PantrySession, Quantity};
+        use std::time::Duration;
+
+        let session = |id: &str| PantrySession { id: id.to_string() };
+        let delivery = || Ingredients::from([("flour".to_string(), Quantity::new(100.0, None))]);
+
+        let pantries = Pantries::with_limits(Duration::from_secs(3600), 2);
+        for id in ["a", "b", "c"] {
+            pantries.restock(&session(id), delivery()).unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert!(pantries.get(&session("a")).unwrap().is_empty());
+        assert!(!pantries.get(&session("c")).unwrap().is_empty());
+        assert_eq!(2, pantries.store.read().unwrap().len());
+
+        let pantries = Pantries::with_limits(Duration::ZERO, 10);
+        pantries.restock(&session("a"), delivery()).unwrap();
+        assert!(pantries.get(&session("a")).unwrap().is_empty());
+        pantries.restock(&session("b"), delivery()).unwrap();
+        assert_eq!(1, pantries.store.read().unwrap().len());
+    }
+
+    #[test]
+    fn shopping_list_test() {
+        use base64::engine::general_purpose;
+        use base64::Engine;
+        use rocket::http::Header;
+
+        let client = test_client_stateful(super::routes(), Pantries::new());
+
+        let response = client
+            .post("/7/shopping?cookies=3")
+            .body(r#"{"recipe":{"flour":100,"sugar":50,"eggs":1},"pantry":{"flour":120,"sugar":200}}"#)
+            .dispatch();
+        let body = response.into_string().unwrap();
+
+        assert!(body.contains(r#""flour":180"#), "{body}");
+        assert!(body.contains(r#""eggs":3"#), "{body}");
+        assert!(!body.contains(r#""sugar""#), "{body}");
+        assert!(body.contains(r#""missing":["eggs"]"#), "{body}");
+
+        // A missing ingredient means no cookies, rather than being ignored
+        let response = client
+            .get("/7/bake")
+            .header(Header::new(
+                "Cookie",
+                format!(
+                    "recipe={}",
+                    general_purpose::STANDARD.encode(
+                        r#"{"recipe":{"flour":100,"eggs":1,"salt":0},"pantry":{"flour":500}}"#
+                    )
+                ),
+            ))
+            .dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.contains(r#""cookies":0"#), "{body}");
+        assert!(body.contains(r#""missing":["eggs"]"#), "{body}");
+    }
+
+    #[test]
+    fn scale_recipe_test() {
+        let client = test_client_stateful(super::routes(), Pantries::new());
+
+        let response = client
+            .post("/7/scale?factor=1.5")
+            .body(r#"{"flour":100,"eggs":1}"#)
+            .dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.contains(r#""flour":150"#), "{body}");
+        assert!(body.contains(r#""eggs":1.5"#), "{body}");
+
+        let response = client
+            .post("/7/scale?factor=-1")
+            .body(r#"{"flour":100}"#)
+            .dispatch();
+        assert_eq!(rocket::http::Status::BadRequest, response.status());
+    }
+
+    #[test]
+    fn plan_bakery_test() {
+        let client = test_client_stateful(super::routes(), Pantries::new());
+        let bakery = r#"{
+            "recipes": [
+                {"name": "shortbread", "recipe": {"flour": 3, "butter": 2}, "value": 5},
+                {"name": "meringue", "recipe": {"sugar": 2, "eggs": 1}, "value": 2},
+                {"name": "sponge", "recipe": {"flour": 1, "sugar": 1, "eggs": 1}, "value": 3}
+            ],
+            "pantry": {"flour": 10, "butter": 4, "sugar": 6, "eggs": 4}
+        }"#;
+
+        let response = client.post("/7/plan").body(bakery).dispatch();
+        let body = response.into_string().unwrap();
+        for fragment in [
+            r#""shortbread":2"#,
+            r#""meringue":0"#,
+            r#""sponge":4"#,
+            r#""value":22.0"#,
+            r#""optimal":true"#,
+            r#""flour":0"#,
+            r#""sugar":2"#,
+        ] {
+            assert!(
+                body.contains(fragment),
+                "'{body}' should contain '{fragment}'"
+            );
+        }
+
+        let response = client
+            .post("/7/plan?objective=cookies")
+            .body(bakery)
+            .dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.contains(r#""cookies":6"#), "{body}");
+
+        let recipes: Vec<_> = (0..=super::MAX_PLAN_RECIPES)
+            .map(|i| format!(r#"{{"name": "cookie {i}", "recipe": {{"flour": 1}}}}"#))
+            .collect();
+        let response = client
+            .post("/7/plan")
+            .body(format!(
+                r#"{{"recipes": [{}], "pantry": {{"flour": 10}}}}"#,
+                recipes.join(",")
+            ))
+            .dispatch();
+        assert_eq!(rocket::http::Status::BadRequest, response.status());
+    }
+
+    #[test]
+    fn units_test() {
+        let client = test_client_stateful(super::routes(), Pantries::new());
+
+        let response = client
+            .post("/7/shopping?cookies=4")
+            .body(
+                r#"{
+                    "recipe": {"flour": "250 g", "milk": {"amount": 0.5, "unit": "cups"}, "eggs": "1 piece"},
+                    "pantry": {"flour": "0.8kg", "milk": {"amount": 1, "unit": "l"}, "eggs": "2 pcs"}
+                }"#,
+            )
+            .dispatch();
+        let body = response.into_string().unwrap();
+        assert!(
+            body.contains(r#""flour":{"amount":200,"unit":"g"}"#),
+            "{body}"
+        );
+        assert!(
+            body.contains(r#""eggs":{"amount":2,"unit":"pieces"}"#),
+            "{body}"
+        );
+        assert!(!body.contains("milk"), "{body}");
+
+        let response = client
+            .post("/7/pantry")
+            .body(r#"{"flour":"1.5 kg"}"#)
+            .dispatch();
+        assert_eq!(rocket::http::Status::Ok, response.status());
+        let response = client
+            .post("/7/pantry/bake")
+            .body(r#"{"flour":"400 g"}"#)
+            .dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.contains(r#""cookies":3"#), "{body}");
+        assert!(
+            body.contains(r#""flour":{"amount":0.3,"unit":"kg"}"#),
+            "{body}"
+        );
+
+        for recipe in [r#"{"flour":"1 cup"}"#, r#"{"flour":100}"#] {
+            let response = client.post("/7/pantry/bake").body(recipe).dispatch();
+            assert_eq!(rocket::http::Status::BadRequest, response.status());
+            assert!(response
+                .into_string()
+                .unwrap()
+                .contains("Incompatible units"));
+        }
+    }
 }
@@ -1,47 +1,567 @@
-use rocket::{get, routes, Route};
-use rustemon::client::RustemonClient;
-use rustemon::pokemon::pokemon;
+use std::convert::Infallible;
+use std::time::Duration;
 
-use crate::common::Error;
+use chrono::Utc;
+use reqwest::{StatusCode, Url};
+use rocket::futures::{stream, StreamExt};
+use rocket::request::FromParam;
+use rocket::serde::json::{serde_json, Json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{get, post, routes, FromFormField, Route, State};
+use sqlx::prelude::*;
+use sqlx::SqlitePool;
 
-async fn pokemon_weight_kg(id: i64) -> Result<f64, Error> {
-    let client = RustemonClient::default();
-    let pkm = pokemon::get_by_id(id, &client).await.map_err(|_| Error {
-        message: "Something went wrong",
-    })?;
+use crate::common::{ApiError, Error, InputError};
+
+/// The parts of a Pokémon we care about, in PokéAPI's units
+#[derive(Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+struct Pokemon {
+    id: i64,
+    name: String,
+    /// In hectograms
+    weight: i64,
+    /// In decimetres
+    height: i64,
+}
+
+impl Pokemon {
+    #[allow(clippy::cast_precision_loss)]
+    fn weight_kg(&self) -> f64 {
+        (self.weight as f64) / 10.0
+    }
 
     #[allow(clippy::cast_precision_loss)]
-    Ok((pkm.weight as f64) / 10.0)
+    fn height_m(&self) -> f64 {
+        (self.height as f64) / 10.0
+    }
+}
+
+/// A Pokémon as asked for by the client, either by its id or its name
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", untagged, from = "PokemonRefRepr")]
+enum PokemonRef {
+    Id(i64),
+    Name(String),
+}
+
+/// A Pokémon as written in a request body, before its name is normalised
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum PokemonRefRepr {
+    Id(i64),
+    Name(String),
+}
+
+impl From<PokemonRefRepr> for PokemonRef {
+    fn from(repr: PokemonRefRepr) -> Self {
+        match repr {
+            PokemonRefRepr::Id(id) => Self::Id(id),
+            PokemonRefRepr::Name(name) => Self::name(&name),
+        }
+    }
+}
+
+impl PokemonRef {
+    fn name(name: &str) -> Self {
+        Self::Name(name.trim().to_lowercase())
+    }
+
+    /// The path of this Pokémon under PokéAPI's base URL
+    fn path(&self) -> String {
+        match self {
+            PokemonRef::Id(id) => format!("pokemon/{id}"),
+            PokemonRef::Name(name) => format!("pokemon/{name}"),
+        }
+    }
+
+    fn matches(&self, pkm: &Pokemon) -> bool {
+        match self {
+            PokemonRef::Id(id) => pkm.id == *id,
+            PokemonRef::Name(name) => pkm.name == *name,
+        }
+    }
+}
+
+impl<'a> FromParam<'a> for PokemonRef {
+    type Error = Infallible;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        Ok(param.parse().map_or_else(|_| Self::name(param), Self::Id))
+    }
+}
+
+/// Previously fetched Pokémon, kept in SQLite until they are older than the TTL
+struct PokemonCache {
+    pool: SqlitePool,
+    ttl: Duration,
+}
+
+impl PokemonCache {
+    async fn new(pool: SqlitePool, ttl: Duration) -> Result<Self, Error> {
+        pool.execute(include_str!("../db/schema_8.sql")).await?;
+        Ok(Self { pool, ttl })
+    }
+
+    fn oldest_fresh_timestamp(&self) -> i64 {
+        Utc::now().timestamp() - i64::try_from(self.ttl.as_secs()).unwrap_or(i64::MAX)
+    }
+
+    async fn get(&self, pkm_ref: &PokemonRef) -> Result<Option<Pokemon>, Error> {
+        let query = match pkm_ref {
+            PokemonRef::Id(id) => sqlx::query_as(
+                "SELECT id, name, weight, height FROM pokemon_cache WHERE id = $1 AND fetched_at >= $2",
+            )
+            .bind(*id),
+            PokemonRef::Name(name) => sqlx::query_as(
+                "SELECT id, name, weight, height FROM pokemon_cache WHERE name = $1 AND fetched_at >= $2",
+            )
+            .bind(name.as_str()),
+        };
+        let pkm = query
+            .bind(self.oldest_fresh_timestamp())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(pkm)
+    }
+
+    async fn put(&self, pkm: &Pokemon) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO pokemon_cache (id, name, weight, height, fetched_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(pkm.id)
+        .bind(&pkm.name)
+        .bind(pkm.weight)
+        .bind(pkm.height)
+        .bind(Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+const POKEAPI_BASE_URL: &str = "https://pokeapi.co/api/v2/";
+
+enum Source {
+    /// Asks PokéAPI over plain HTTP, without an HTTP cache of its own, so `PokemonCache` and
+    /// its TTL decide what gets kept
+    Live {
+        base_url: Url,
+        http: reqwest::Client,
+    },
+    /// Canned Pokémon, for running without network access
+    Fixtures(Vec<Pokemon>),
+}
+
+/// Where Pokémon come from, shared between requests
+pub struct PokeApi {
+    source: Source,
+    cache: Option<PokemonCache>,
+}
+
+impl PokeApi {
+    /// Talks to PokéAPI, or a stand-in for it at `base_url`
+    pub fn live(base_url: Option<String>) -> Result<Self, Error> {
+        let invalid = |e| {
+            if cfg!(debug_assertions) {
+                dbg!(e);
+            }
+            Error {
+                message: "Invalid PokéAPI base URL",
+            }
+        };
+        let mut base_url = base_url.unwrap_or_else(|| POKEAPI_BASE_URL.to_string());
+        // Paths are joined onto the base, which drops its last segment without a slash
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        let base_url = Url::parse(&base_url).map_err(invalid)?;
+
+        Ok(Self {
+            source: Source::Live {
+                base_url,
+                http: reqwest::Client::new(),
+            },
+            cache: None,
+        })
+    }
+
+    /// Serves the Pokémon listed in a JSON fixture instead of calling out
+    pub fn fixtures(json: &str) -> Result<Self, Error> {
+        Ok(Self {
+            source: Source::Fixtures(serde_json::from_str(json)?),
+            cache: None,
+        })
+    }
+
+    pub async fn with_cache(mut self, pool: SqlitePool, ttl: Duration) -> Result<Self, Error> {
+        self.cache = Some(PokemonCache::new(pool, ttl).await?);
+        Ok(self)
+    }
+
+    async fn fetch(&self, pkm_ref: &PokemonRef) -> Result<Pokemon, ApiError> {
+        let not_found = || ApiError::NotFound("Unknown Pokémon");
+
+        match &self.source {
+            Source::Live { base_url, http } => {
+                let broken = |e: reqwest::Error| {
+                    if cfg!(debug_assertions) {
+                        dbg!(e);
+                    }
+                    ApiError::from(Error {
+                        message: "Something went wrong",
+                    })
+                };
+                let url = base_url.join(&pkm_ref.path()).map_err(|_| Error {
+                    message: "Invalid PokéAPI URL",
+                })?;
+                let response = http.get(url).send().await.map_err(broken)?;
+                // PokéAPI answers unknown Pokémon with a plain "Not Found" body
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Err(not_found());
+                }
+                response
+                    .error_for_status()
+                    .map_err(broken)?
+                    .json()
+                    .await
+                    .map_err(broken)
+            }
+            Source::Fixtures(pokemon) => pokemon
+                .iter()
+                .find(|pkm| pkm_ref.matches(pkm))
+                .cloned()
+                .ok_or_else(not_found),
+        }
+    }
+
+    async fn pokemon(&self, pkm_ref: &PokemonRef) -> Result<Pokemon, ApiError> {
+        if let Some(cache) = &self.cache {
+            if let Some(pkm) = cache.get(pkm_ref).await? {
+                return Ok(pkm);
+            }
+        }
+
+        let pkm = self.fetch(pkm_ref).await?;
+
+        if let Some(cache) = &self.cache {
+            cache.put(&pkm).await?;
+        }
+
+        Ok(pkm)
+    }
+}
+
+#[get("/8/weight/<pokemon>")]
+async fn pokemon_weight(api: &State<PokeApi>, pokemon: PokemonRef) -> Result<String, ApiError> {
+    Ok(api.pokemon(&pokemon).await?.weight_kg().to_string())
+}
+
+#[derive(FromFormField, Clone, Copy, Default)]
+enum Planet {
+    Mercury,
+    Venus,
+    #[default]
+    Earth,
+    Moon,
+    Mars,
+    Jupiter,
+    Saturn,
+    Uranus,
+    Neptune,
+}
+
+impl Planet {
+    /// Surface gravity in m/s²
+    fn gravity(self) -> f64 {
+        match self {
+            Planet::Mercury => 3.7,
+            Planet::Venus => 8.87,
+            Planet::Earth => 9.825,
+            Planet::Moon => 1.62,
+            Planet::Mars => 3.721,
+            Planet::Jupiter => 24.79,
+            Planet::Saturn => 10.44,
+            Planet::Uranus => 8.69,
+            Planet::Neptune => 11.15,
+        }
+    }
+
+    /// Air density at the surface in kg/m³, or at 1 bar for the gas giants
+    fn air_density(self) -> f64 {
+        match self {
+            Planet::Mercury | Planet::Moon => 0.0,
+            Planet::Venus => 65.0,
+            Planet::Earth => 1.225,
+            Planet::Mars => 0.020,
+            Planet::Jupiter => 0.16,
+            Planet::Saturn => 0.19,
+            Planet::Uranus => 0.42,
+            Planet::Neptune => 0.45,
+        }
+    }
+}
+
+/// Drag coefficient of a sphere, which is what every Pokémon is as far as we're concerned
+const DRAG_COEFFICIENT: f64 = 0.47;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Fall {
+    /// Seconds until impact
+    time: f64,
+    /// Impact velocity in m/s
+    velocity: f64,
+    /// In kg·m/s
+    momentum: f64,
+    /// In joules
+    kinetic_energy: f64,
+    /// In m/s, if the fall was slowed by drag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terminal_velocity: Option<f64>,
+}
+
+impl Fall {
+    /// Falling from `height` metres at `gravity` m/s², optionally against air resistance
+    /// through a medium of density `air_density` for a sphere with the Pokémon's height as
+    /// its diameter
+    fn simulate(
+        pkm: &Pokemon,
+        height: f64,
+        gravity: f64,
+        air_density: Option<f64>,
+    ) -> Result<Self, InputError> {
+        if !height.is_finite() || height <= 0.0 {
+            return Err(InputError {
+                message: "Drop height must be a positive number of metres",
+            });
+        }
+        if !gravity.is_finite() || gravity <= 0.0 {
+            return Err(InputError {
+                message: "Gravity must be a positive number of m/s²",
+            });
+        }
+
+        let mass = pkm.weight_kg();
+        let area = std::f64::consts::PI * (pkm.height_m() / 2.0).powi(2);
+        let drag = air_density
+            .map(|density| density * DRAG_COEFFICIENT * area)
+            .filter(|drag| *drag > 0.0);
+
+        let (time, velocity, terminal_velocity) = match drag {
+            None => {
+                let velocity = (2.0f64 * height * gravity).sqrt();
+                (velocity / gravity, velocity, None)
+            }
+            Some(_) if mass <= 0.0 => {
+                return Err(InputError {
+                    message: "Can't simulate drag on a weightless Pokémon",
+                })
+            }
+            Some(drag) => {
+                // Quadratic drag has closed forms for velocity and distance over time
+                let terminal = (2.0 * mass * gravity / drag).sqrt();
+                let x = height * gravity / terminal.powi(2);
+                // acosh(e^x) approaches x + ln 2 long before e^x overflows
+                let acosh_exp = if x > 20.0 {
+                    x + std::f64::consts::LN_2
+                } else {
+                    x.exp().acosh()
+                };
+                let time = terminal / gravity * acosh_exp;
+                let velocity = terminal * (1.0 - (-2.0 * x).exp()).sqrt();
+                (time, velocity, Some(terminal))
+            }
+        };
+
+        Ok(Self {
+            time,
+            velocity,
+            momentum: velocity * mass,
+            kinetic_energy: 0.5 * mass * velocity.powi(2),
+            terminal_velocity,
+        })
+    }
+}
+
+#[get("/8/drop/<pokemon>")]
+async fn pokemon_drop(api: &State<PokeApi>, pokemon: PokemonRef) -> Result<String, ApiError> {
+    let pkm = api.pokemon(&pokemon).await?;
+    let fall = Fall::simulate(&pkm, 10.0, Planet::Earth.gravity(), None)?;
+
+    Ok(fall.momentum.to_string())
+}
+
+/// `gravity` overrides the planet's own gravity, but air density still comes from the planet
+#[get("/8/drop/<pokemon>/simulate?<height>&<gravity>&<planet>&<drag>")]
+async fn pokemon_drop_simulation(
+    api: &State<PokeApi>,
+    pokemon: PokemonRef,
+    height: Option<f64>,
+    gravity: Option<f64>,
+    planet: Option<Planet>,
+    drag: Option<bool>,
+) -> Result<Json<Fall>, ApiError> {
+    let pkm = api.pokemon(&pokemon).await?;
+    let planet = planet.unwrap_or_default();
+    let fall = Fall::simulate(
+        &pkm,
+        height.unwrap_or(10.0),
+        gravity.unwrap_or_else(|| planet.gravity()),
+        drag.unwrap_or(false).then(|| planet.air_density()),
+    )?;
+
+    Ok(Json(fall))
 }
 
-#[get("/8/weight/<id>")]
-async fn pokemon_weight(id: i64) -> Result<String, Error> {
-    Ok(pokemon_weight_kg(id).await?.to_string())
+/// Most Pokémon looked up in a single batch
+const BATCH_LIMIT: usize = 100;
+/// Most lookups in flight at once for a batch
+const BATCH_CONCURRENCY: usize = 8;
+
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+struct FoundPokemon {
+    id: i64,
+    name: String,
+    /// In kg
+    weight: f64,
+    /// In m
+    height: f64,
 }
 
-#[get("/8/drop/<id>")]
-async fn pokemon_drop(id: i64) -> Result<String, Error> {
-    let mass = pokemon_weight_kg(id).await?;
-    let height = 10.0;
-    let gravitational_acceleration = 9.825;
-    // Thanks ChatGPT for these formulas
-    let velocity = (2.0f64 * height * gravitational_acceleration).sqrt();
-    let momentum = velocity * mass;
+impl From<Pokemon> for FoundPokemon {
+    fn from(pkm: Pokemon) -> Self {
+        Self {
+            id: pkm.id,
+            weight: pkm.weight_kg(),
+            height: pkm.height_m(),
+            name: pkm.name,
+        }
+    }
+}
 
-    Ok(momentum.to_string())
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum Lookup {
+    Pokemon(FoundPokemon),
+    Error(&'static str),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchItem {
+    query: PokemonRef,
+    #[serde(flatten)]
+    lookup: Lookup,
+}
+
+impl PokeApi {
+    /// Looks up every Pokémon, a few at a time, keeping the order they were asked for in
+    async fn batch(&self, queries: Vec<PokemonRef>) -> Result<Vec<BatchItem>, InputError> {
+        if queries.len() > BATCH_LIMIT {
+            return Err(InputError {
+                message: "Too many Pokémon in one batch",
+            });
+        }
+
+        Ok(stream::iter(queries)
+            .map(|query| async move {
+                let lookup = match self.pokemon(&query).await {
+                    Ok(pkm) => Lookup::Pokemon(pkm.into()),
+                    Err(
+                        ApiError::NotFound(message)
+                        | ApiError::Unauthorized(message)
+                        | ApiError::Conflict(message)
+                        | ApiError::Input(InputError { message })
+                        | ApiError::Server(Error { message }),
+                    ) => Lookup::Error(message),
+                };
+                BatchItem { query, lookup }
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await)
+    }
+}
+
+#[post("/8/batch", data = "<queries>")]
+async fn pokemon_batch(
+    api: &State<PokeApi>,
+    queries: Json<Vec<PokemonRef>>,
+) -> Result<Json<Vec<BatchItem>>, InputError> {
+    Ok(Json(api.batch(queries.into_inner()).await?))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchStats {
+    count: usize,
+    /// In kg
+    total_weight: f64,
+    heaviest: Option<FoundPokemon>,
+    lightest: Option<FoundPokemon>,
+    errors: Vec<BatchItem>,
+}
+
+impl From<Vec<BatchItem>> for BatchStats {
+    fn from(items: Vec<BatchItem>) -> Self {
+        let (found, errors): (Vec<_>, Vec<_>) = items
+            .into_iter()
+            .partition(|item| matches!(item.lookup, Lookup::Pokemon(_)));
+        let mut found: Vec<_> = found
+            .into_iter()
+            .filter_map(|item| match item.lookup {
+                Lookup::Pokemon(pkm) => Some(pkm),
+                Lookup::Error(_) => None,
+            })
+            .collect();
+        found.sort_by(|a, b| a.weight.total_cmp(&b.weight));
+
+        Self {
+            count: found.len(),
+            total_weight: found.iter().map(|pkm| pkm.weight).sum(),
+            heaviest: found.last().cloned(),
+            lightest: found.first().cloned(),
+            errors,
+        }
+    }
+}
+
+#[post("/8/stats", data = "<queries>")]
+async fn pokemon_stats(
+    api: &State<PokeApi>,
+    queries: Json<Vec<PokemonRef>>,
+) -> Result<Json<BatchStats>, InputError> {
+    Ok(Json(api.batch(queries.into_inner()).await?.into()))
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![pokemon_weight, pokemon_drop]
+    routes![
+        pokemon_weight,
+        pokemon_drop,
+        pokemon_drop_simulation,
+        pokemon_batch,
+        pokemon_stats
+    ]
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::common::test_client;
+    use std::time::Duration;
+
+    use sqlx::SqlitePool;
+
+    use super::{PokeApi, PokemonRef};
+    use crate::common::test_client_stateful;
+
+    fn fixture_api() -> PokeApi {
+        PokeApi::fixtures(include_str!("../fixtures/pokemon.json")).unwrap()
+    }
 
     #[test]
     fn pokemon_weight_test() {
-        let client = test_client(super::routes());
+        let client = test_client_stateful(super::routes(), fixture_api());
         let response = client.get("/8/weight/25").dispatch();
 
         assert_eq!("6", response.into_string().unwrap());
@@ -49,9 +569,203 @@ mod tests {
 
     #[test]
     fn pokemon_drop_test() {
-        let client = test_client(super::routes());
+        let client = test_client_stateful(super::routes(), fixture_api());
         let response = client.get("/8/drop/25").dispatch();
 
         assert_eq!("84.10707461325713", response.into_string().unwrap());
     }
+
+    #[test]
+    fn pokemon_drop_simulation_test() {
+        use rocket::http::Status;
+        use rocket::serde::json::{serde_json, Value};
+
+        let client = test_client_stateful(super::routes(), fixture_api());
+        let simulate = |query: &str| {
+            let response = client
+                .get(format!("/8/drop/25/simulate?{query}"))
+                .dispatch();
+            let status = response.status();
+            let body: Value =
+                serde_json::from_str(&response.into_string().unwrap()).unwrap_or(Value::Null);
+            (status, body)
+        };
+
+        let (_, vacuum) = simulate("");
+        assert_eq!(84.107_074_613_257_13, vacuum["momentum"]);
+        assert!(vacuum.get("terminal_velocity").is_none());
+
+        let (_, moon) = simulate("planet=moon&height=20&drag=true");
+        let velocity = (2.0f64 * 20.0 * 1.62).sqrt();
+        assert!((moon["velocity"].as_f64().unwrap() - velocity).abs() < 1e-9);
+        assert!((moon["time"].as_f64().unwrap() - velocity / 1.62).abs() < 1e-9);
+
+        let (_, air) = simulate("drag=true&height=1000");
+        let terminal = air["terminal_velocity"].as_f64().unwrap();
+        assert!(air["velocity"].as_f64().unwrap() < terminal);
+        assert!(air["velocity"].as_f64().unwrap() > terminal * 0.99);
+        assert!(air["time"].as_f64().unwrap() > vacuum["time"].as_f64().unwrap());
+
+        for query in ["height=-1", "gravity=0", "height=NaN"] {
+            assert_eq!(Status::BadRequest, simulate(query).0, "{query}");
+        }
+    }
+
+    #[test]
+    fn pokemon_by_name_test() {
+        use rocket::http::Status;
+
+        let client = test_client_stateful(super::routes(), fixture_api());
+        let response = client.get("/8/weight/Snorlax").dispatch();
+        assert_eq!("460", response.into_string().unwrap());
+
+        let response = client.get("/8/weight/missingno").dispatch();
+        assert_eq!(Status::NotFound, response.status());
+    }
+
+    #[test]
+    fn pokemon_batch_test() {
+        let client = test_client_stateful(super::routes(), fixture_api());
+        let response = client
+            .post("/8/batch")
+            .body(r#"[25, "mew", "missingno"]"#)
+            .dispatch();
+        assert_eq!(
+            r#"[{"query":25,"pokemon":{"id":25,"name":"pikachu","weight":6.0,"height":0.4}},{"query":"mew","pokemon":{"id":151,"name":"mew","weight":4.0,"height":0.4}},{"query":"missingno","error":"Unknown Pokémon"}]"#,
+            response.into_string().unwrap()
+        );
+
+        let response = client.post("/8/stats").body(r#"[1, 6, 143, 0]"#).dispatch();
+        let body = response.into_string().unwrap();
+        for fragment in [
+            r#""count":3"#,
+            r#""total_weight":557.4"#,
+            r#""heaviest":{"id":143"#,
+            r#""lightest":{"id":1"#,
+            r#""errors":[{"query":0,"error":"Unknown Pokémon"}]"#,
+        ] {
+            assert!(
+                body.contains(fragment),
+                "'{body}' should contain '{fragment}'"
+            );
+        }
+    }
+
+    #[test]
+    fn pokemon_body_names_test() {
+        let client = test_client_stateful(super::routes(), fixture_api());
+        let response = client
+            .post("/8/batch")
+            .body(r#"[" Pikachu ", "MEW"]"#)
+            .dispatch();
+        let body = response.into_string().unwrap();
+        assert!(
+            body.contains(r#""query":"pikachu","pokemon":{"id":25"#),
+            "{body}"
+        );
+        assert!(
+            body.contains(r#""query":"mew","pokemon":{"id":151"#),
+            "{body}"
+        );
+
+        // With one Pokémon found, it's both the heaviest and the lightest
+        let response = client.post("/8/stats").body(r#"["Snorlax"]"#).dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.contains(r#""count":1"#), "{body}");
+        assert!(body.contains(r#""heaviest":{"id":143"#), "{body}");
+        assert!(body.contains(r#""lightest":{"id":143"#), "{body}");
+    }
+
+    #[rocket::async_test]
+    async fn pokemon_live_errors_test() {
+        use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use rocket::tokio::net::TcpListener;
+
+        use crate::common::ApiError;
+
+        // Stands in for PokéAPI: unknown Pokémon get a 404, anything else a body that isn't JSON
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        rocket::tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let response = if String::from_utf8_lossy(&request[..read]).contains("/missingno") {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\nNot Found"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nbroken"
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let api = PokeApi::live(Some(format!("http://{addr}/api/v2/"))).unwrap();
+        assert!(matches!(
+            api.pokemon(&PokemonRef::name("missingno")).await,
+            Err(ApiError::NotFound(_))
+        ));
+        assert!(matches!(
+            api.pokemon(&PokemonRef::Id(25)).await,
+            Err(ApiError::Server(_))
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn pokemon_cache_test() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let api = fixture_api()
+            .with_cache(pool.clone(), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert_eq!(60, api.pokemon(&PokemonRef::Id(25)).await.unwrap().weight);
+
+        // A cached entry wins over the source while it's fresh
+        sqlx::query("UPDATE pokemon_cache SET weight = 61 WHERE id = 25")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            61,
+            api.pokemon(&PokemonRef::name("Pikachu"))
+                .await
+                .unwrap()
+                .weight
+        );
+
+        // Once it's older than the TTL, the source is asked again
+        sqlx::query("UPDATE pokemon_cache SET fetched_at = fetched_at - 120")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let pkm = api.pokemon(&PokemonRef::Id(25)).await.unwrap();
+        assert_eq!(60, pkm.weight);
+    }
+
+    #[rocket::async_test]
+    async fn pokemon_cache_file_test() {
+        use crate::common::open_database;
+
+        let path = std::env::temp_dir().join(format!("cch23-pokemon-{}.sqlite", ulid::Ulid::new()));
+        let path = path.to_str().unwrap();
+
+        let api = fixture_api()
+            .with_cache(open_database(path).await.unwrap(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        api.pokemon(&PokemonRef::Id(25)).await.unwrap();
+        drop(api);
+
+        // A fresh pool on the same file, as after a restart, still has the entry
+        let pool = open_database(path).await.unwrap();
+        let api = PokeApi::fixtures("[]")
+            .unwrap()
+            .with_cache(pool.clone(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(60, api.pokemon(&PokemonRef::Id(25)).await.unwrap().weight);
+
+        pool.close().await;
+        std::fs::remove_file(path).unwrap();
+    }
 }
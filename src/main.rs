@@ -1,8 +1,7 @@
 #![allow(clippy::needless_pass_by_value)]
 #![allow(clippy::no_effect_underscore_binding)]
 
-use rocket_dyn_templates::Template;
-use sqlx::sqlite::SqlitePoolOptions;
+use std::time::Duration;
 
 mod common;
 mod day_0;
@@ -22,23 +21,68 @@ mod day_5;
 mod day_6;
 mod day_7;
 mod day_8;
+mod security;
 
-use common::DB;
+use common::{open_database, DB};
+use day_11::{AssetsConfig, ImageLimits};
 use day_12::Timekeeper;
-use day_19::ChatState;
+use day_14::CardTemplates;
+use day_15::accounts::{Accounts, AccountsConfig};
+use day_15::{Breaches, RuleSets};
+use day_19::{ChatConfig, ChatState};
 use day_21::GeocodeApiKey;
+use day_7::Pantries;
+use day_8::PokeApi;
+use security::SecurityHeaders;
 
 #[allow(clippy::unused_async)]
 #[shuttle_runtime::main]
 async fn main(
     #[shuttle_runtime::Secrets] secrets: shuttle_runtime::SecretStore,
 ) -> shuttle_rocket::ShuttleRocket {
-    // Create an in-memory SQLite database
-    let pool = SqlitePoolOptions::new()
-        .connect("sqlite::memory:")
-        .await
-        .expect("Couldn't start sqlite pool");
+    // Templates, accounts and chat history live in a file, so they outlive the process and
+    // every pooled connection sees the same tables
+    let pool = open_database(
+        &secrets
+            .get("DATABASE_PATH")
+            .unwrap_or_else(|| "cch23.sqlite".to_string()),
+    )
+    .await
+    .expect("Couldn't start sqlite pool");
     let key = secrets.get("GEOCODE_API_KEY").expect("Couldn't get secret");
+    // Point POKEAPI_FIXTURES at a JSON file of Pokémon to run without network access
+    let pokeapi = match secrets.get("POKEAPI_FIXTURES") {
+        Some(path) => PokeApi::fixtures(
+            &std::fs::read_to_string(path).expect("Couldn't read PokéAPI fixtures"),
+        ),
+        None => PokeApi::live(secrets.get("POKEAPI_BASE_URL")),
+    }
+    .expect("Couldn't set up PokéAPI client");
+    let pokeapi_ttl = secrets
+        .get("POKEAPI_CACHE_TTL")
+        .map_or(Ok(60 * 60 * 24), |ttl| ttl.parse())
+        .expect("POKEAPI_CACHE_TTL should be a number of seconds");
+    // Cached Pokémon are kept in their own file so they survive restarts
+    let pokeapi_cache = open_database(
+        &secrets
+            .get("POKEAPI_CACHE_PATH")
+            .unwrap_or_else(|| "pokeapi_cache.sqlite".to_string()),
+    )
+    .await
+    .expect("Couldn't open PokéAPI cache database");
+    let pokeapi = pokeapi
+        .with_cache(pokeapi_cache, Duration::from_secs(pokeapi_ttl))
+        .await
+        .expect("Couldn't set up PokéAPI cache");
+    let card_templates = CardTemplates::new(pool.clone())
+        .await
+        .expect("Couldn't set up template storage");
+    let accounts = Accounts::new(pool.clone())
+        .await
+        .expect("Couldn't set up account storage");
+    let chat_state = ChatState::new(pool.clone())
+        .await
+        .expect("Couldn't set up chat history");
     let rocket = rocket::build()
         .mount("/", day_0::routes())
         .mount("/", day_1::routes())
@@ -52,6 +96,7 @@ async fn main(
         .mount("/", day_13::routes())
         .mount("/14", day_14::routes())
         .mount("/15", day_15::routes())
+        .mount("/15/accounts", day_15::accounts::routes())
         .mount("/18", day_18::routes())
         .mount("/19", day_19::routes())
         .mount("/20", day_20::routes())
@@ -59,9 +104,20 @@ async fn main(
         .mount("/22", day_22::routes())
         .manage(Timekeeper::new())
         .manage(DB { pool })
-        .manage(ChatState::new())
+        .manage(chat_state)
         .manage(GeocodeApiKey { key })
-        .attach(Template::fairing());
+        .manage(Pantries::new())
+        .manage(pokeapi)
+        .manage(card_templates)
+        .manage(accounts)
+        .attach(ImageLimits::fairing())
+        .attach(AssetsConfig::fairing())
+        .attach(RuleSets::fairing())
+        .attach(Breaches::fairing())
+        .attach(AccountsConfig::fairing())
+        .attach(ChatConfig::fairing())
+        .attach(day_14::templates())
+        .attach(SecurityHeaders::fairing());
 
     Ok(rocket.into())
 }
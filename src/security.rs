@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::request::{self, FromRequest};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::shield::{Frame, Shield};
+use rocket::{Request, Response};
+
+use crate::common::read_config;
+
+/// Where the request's nonce goes in a Content-Security-Policy
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// A random value for this request only, which inline scripts and styles need to carry in a
+/// `nonce` attribute to be allowed by the Content-Security-Policy
+#[derive(Serialize, Debug)]
+#[serde(crate = "rocket::serde", transparent)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        let mut bytes = [0; 16];
+        // Without randomness the nonce would be guessable, so allow no inline content instead
+        if SystemRandom::new().fill(&mut bytes).is_err() {
+            return Self(String::new());
+        }
+        Self(STANDARD_NO_PAD.encode(bytes))
+    }
+
+    fn for_request<'r>(req: &'r Request<'_>) -> &'r Self {
+        req.local_cache(Self::generate)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r CspNonce {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(CspNonce::for_request(req))
+    }
+}
+
+/// Headers for one route that differ from the defaults, or `disabled` to send none at all
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct RoutePolicy {
+    disabled: bool,
+    content_security_policy: Option<String>,
+    referrer_policy: Option<String>,
+    frame_options: Option<String>,
+}
+
+/// The security headers added to HTML responses, apart from frame options which every
+/// response gets
+#[derive(Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SecurityConfig {
+    /// `{nonce}` is replaced with the response's nonce
+    content_security_policy: String,
+    referrer_policy: String,
+    frame_options: String,
+    /// Overrides keyed by the route's path as mounted, e.g. `/14/unsafe`
+    routes: HashMap<String, RoutePolicy>,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            content_security_policy: "default-src 'self'; script-src 'nonce-{nonce}'; \
+                style-src 'self' 'nonce-{nonce}'; object-src 'none'; base-uri 'none'; \
+                frame-ancestors 'none'"
+                .into(),
+            referrer_policy: "no-referrer".into(),
+            frame_options: "DENY".into(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+/// Adds security headers to HTML responses, and frame options to every response
+pub struct SecurityHeaders(SecurityConfig);
+
+impl SecurityHeaders {
+    /// Reads the `security_headers` config and attaches the fairing using it
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Security headers config", |rocket| async move {
+            match read_config::<SecurityConfig>(rocket.figment(), "security_headers") {
+                Some(config) => Ok(rocket.attach(Self::shield()).attach(Self(config))),
+                None => Err(rocket),
+            }
+        })
+    }
+
+    /// Rocket's default shield would set frame options before we can, so leave those to us
+    fn shield() -> Shield {
+        Shield::default().disable::<Frame>()
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let policy = req
+            .route()
+            .and_then(|route| self.0.routes.get(route.uri.path()));
+        if policy.is_some_and(|policy| policy.disabled) {
+            return;
+        }
+        let pick =
+            |specific: Option<&String>, default: &String| specific.unwrap_or(default).clone();
+        // An empty value switches the header off, and handlers can set their own
+        let set = |res: &mut Response<'r>, name: &'static str, value: String| {
+            if !value.is_empty() && !res.headers().contains(name) {
+                res.set_header(Header::new(name, value));
+            }
+        };
+
+        // This stands in for Shield's frame options, so it goes on every response
+        set(
+            res,
+            "X-Frame-Options",
+            pick(
+                policy.and_then(|p| p.frame_options.as_ref()),
+                &self.0.frame_options,
+            ),
+        );
+
+        if res.content_type() != Some(ContentType::HTML) {
+            return;
+        }
+
+        let csp = pick(
+            policy.and_then(|p| p.content_security_policy.as_ref()),
+            &self.0.content_security_policy,
+        );
+        let headers = [
+            (
+                "Content-Security-Policy",
+                csp.replace(NONCE_PLACEHOLDER, CspNonce::for_request(req).as_str()),
+            ),
+            ("X-Content-Type-Options", "nosniff".into()),
+            (
+                "Referrer-Policy",
+                pick(
+                    policy.and_then(|p| p.referrer_policy.as_ref()),
+                    &self.0.referrer_policy,
+                ),
+            ),
+        ];
+        for (name, value) in headers {
+            set(res, name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rocket::local::blocking::Client;
+    use rocket::response::content::RawHtml;
+    use rocket::{get, routes};
+
+    use super::{CspNonce, RoutePolicy, SecurityConfig, SecurityHeaders};
+
+    #[get("/page")]
+    fn page(nonce: &CspNonce) -> RawHtml<String> {
+        RawHtml(format!("<script nonce=\"{}\"></script>", nonce.as_str()))
+    }
+
+    #[get("/embeddable")]
+    fn embeddable() -> RawHtml<&'static str> {
+        RawHtml("<p>frame me</p>")
+    }
+
+    #[get("/data")]
+    fn data() -> &'static str {
+        "plain"
+    }
+
+    fn client() -> Client {
+        let config = SecurityConfig {
+            routes: HashMap::from([(
+                "/embeddable".into(),
+                RoutePolicy {
+                    frame_options: Some("SAMEORIGIN".into()),
+                    referrer_policy: Some(String::new()),
+                    ..RoutePolicy::default()
+                },
+            )]),
+            ..SecurityConfig::default()
+        };
+        Client::tracked(
+            rocket::build()
+                .mount("/", routes![page, embeddable, data])
+                .attach(SecurityHeaders::shield())
+                .attach(SecurityHeaders(config)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn security_headers_test() {
+        let client = client();
+
+        let response = client.get("/page").dispatch();
+        let headers = response.headers();
+        let csp = headers
+            .get_one("Content-Security-Policy")
+            .unwrap()
+            .to_string();
+        assert_eq!(Some("nosniff"), headers.get_one("X-Content-Type-Options"));
+        assert_eq!(Some("no-referrer"), headers.get_one("Referrer-Policy"));
+        assert_eq!(Some("DENY"), headers.get_one("X-Frame-Options"));
+        let body = response.into_string().unwrap();
+        let nonce = body
+            .strip_prefix("<script nonce=\"")
+            .and_then(|rest| rest.strip_suffix("\"></script>"))
+            .unwrap();
+        assert_eq!(22, nonce.len());
+        assert!(
+            csp.contains(&format!("script-src 'nonce-{nonce}'")),
+            "{csp}"
+        );
+
+        // Every response gets a fresh nonce
+        let other = client.get("/page").dispatch().into_string().unwrap();
+        assert_ne!(body, other);
+
+        let response = client.get("/embeddable").dispatch();
+        let headers = response.headers();
+        assert_eq!(Some("SAMEORIGIN"), headers.get_one("X-Frame-Options"));
+        assert_eq!(None, headers.get_one("Referrer-Policy"));
+        assert!(headers.contains("Content-Security-Policy"));
+
+        let response = client.get("/data").dispatch();
+        assert!(!response.headers().contains("Content-Security-Policy"));
+        assert_eq!(Some("DENY"), response.headers().get_one("X-Frame-Options"));
+    }
+}